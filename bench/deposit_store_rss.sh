@@ -0,0 +1,59 @@
+#!/usr/bin/env bash
+# Shows how peak memory (RSS) grows with the number of deposits, with and
+# without spilling deposits to disk. Linux only as it reads /proc.
+#
+# Usage: bench/deposit_store_rss.sh [rows...]
+# e.g:   bench/deposit_store_rss.sh 1000000 10000000 100000000
+#
+# Keeping every deposit in memory takes around 180 bytes a row, so runs
+# without the store are skipped beyond MEMORY_MAX_ROWS rows, if set, e.g
+# MEMORY_MAX_ROWS=10000000 on a machine with less than 18 GB for 100M rows.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+cargo build --release --quiet
+bin=target/release/paperstack
+store=$(mktemp)
+trap 'rm -f "$store"' EXIT
+
+# Deposits spread over 1000 clients, with every 10000th deposit disputing
+# one made 500000 rows earlier, i.e long since spilled to disk. Every dispute
+# opens a case that is kept for the cases report, so disputes are kept few
+# for the store to account for what memory is used. Only deposits are spilled,
+# so input with many cases, reserves, authorizations or transactions held for
+# review grows in memory regardless.
+generate() {
+  awk -v rows="$1" 'BEGIN {
+    print "type,client,tx,amount"
+    for (tx = 1; tx <= rows; tx++) {
+      print "deposit," tx % 1000 "," tx ",1.0"
+      if (tx % 10000 == 0 && tx > 500000) print "dispute," (tx - 500000) % 1000 "," tx - 500000 ","
+    }
+  }'
+}
+
+# Runs paperstack over generated input, printing its peak RSS in kB.
+peak_rss() {
+  local rows=$1
+  shift
+  generate "$rows" | "$bin" "$@" /dev/stdin >/dev/null 2>&1 &
+  local pid=$! peak=0 hwm
+  while hwm=$(awk '/VmHWM/ { print $2 }' "/proc/$pid/status" 2>/dev/null) && [ -n "$hwm" ]; do
+    peak=$hwm
+    sleep 0.2
+  done
+  wait "$pid" || true
+  echo "$peak"
+}
+
+[ $# -gt 0 ] || set -- 1000000 5000000 10000000
+
+printf "%12s %16s %16s\n" rows "memory (kB)" "tiered (kB)"
+for rows in "$@"; do
+  memory=-
+  if [ -z "${MEMORY_MAX_ROWS:-}" ] || [ "$rows" -le "$MEMORY_MAX_ROWS" ]; then
+    memory=$(peak_rss "$rows")
+  fi
+  printf "%12s %16s %16s\n" "$rows" "$memory" \
+    "$(peak_rss "$rows" --deposit-store "$store" --deposit-cache 100000)"
+done
@@ -1,26 +1,162 @@
 use anyhow::{anyhow, Result};
 use rust_decimal::Decimal;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
 
+use crate::case::{check_move, Case, Stage};
+use crate::clock::{has_elapsed, Timestamp, Windows};
+use crate::fee::FeeSchedule;
+use crate::history::HistoryEntry;
+use crate::hold::{HoldLine, HoldReason};
+use crate::ledger::{transfer, LedgerAccount, Posting};
+use crate::limit::{Limits, Usage, Withdrawals};
+use crate::overdraft::Overdraft;
+use crate::ratio::Tally;
+use crate::reserve::Reserve;
+use crate::risk::Activity;
+use crate::store::DepositStore;
+use crate::transaction::TimedTransaction;
 use crate::Transaction;
 use serde::{Serialize, Serializer};
 
-#[derive(Default, Serialize, Debug)]
+// What a transaction about the deposit (or withdrawal) tx may change about the
+// account before the store has recorded it: balances, which are rolled back by
+// undoing the postings made since, its holds, its reserve and the lock.
+struct Checkpoint {
+    tx: u32,
+    postings: usize,
+    locked: bool,
+    holds: [Option<Decimal>; 4],
+    reserve: Option<Option<Timestamp>>,
+}
+
+const HOLD_REASONS: [HoldReason; 4] = [
+    HoldReason::Dispute,
+    HoldReason::Authorization,
+    HoldReason::Reserve,
+    HoldReason::Legal,
+];
+
+#[derive(Default, Serialize, Debug, Clone)]
 pub struct Account {
     client: u16,
     #[serde(serialize_with = "serialize_with_fixed_digits")]
     available: Decimal,
+    // The sum of every hold, see holds.
     #[serde(serialize_with = "serialize_with_fixed_digits")]
     held: Decimal,
     #[serde(serialize_with = "serialize_with_fixed_digits")]
     total: Decimal,
     locked: bool,
+    // How much the client owes us beyond any approved overdraft, i.e the part
+    // of a negative available balance that isn't covered by the credit line.
+    // Only chargebacks can put an account in debt and only when the policy
+    // allows it, so under any other policy there is no debt to speak of and
+    // no debt column in the output.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_with_fixed_digits"
+    )]
+    debt: Option<Decimal>,
+    // How much of the approved credit line is currently in use. Like debt,
+    // there is no overdraft column unless overdrafts are configured.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_with_fixed_digits"
+    )]
+    overdraft: Option<Decimal>,
+    // How much of held is a rolling reserve rather than for disputes or authorizations.
+    // Only there with reserves configured, like the overdraft column.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_with_fixed_digits"
+    )]
+    reserved: Option<Decimal>,
+    // Authorizations that still hold funds, tracked like deposits but keyed
+    // by authorization id.
+    #[serde(skip)]
+    authorizations: HashMap<u32, TimedTransaction>,
+    #[serde(skip)]
+    negative_balance_policy: NegativeBalancePolicy,
+    #[serde(skip)]
+    overdraft_limit: Overdraft,
+    #[serde(skip)]
+    windows: Windows,
+    #[serde(skip)]
+    reserve: Reserve,
+    // When what is reserved from each deposit is released, by deposit tx id.
+    #[serde(skip)]
+    reserves: HashMap<u32, Option<Timestamp>>,
+    // What is held, by reason and id.
+    #[serde(skip)]
+    holds: BTreeMap<(HoldReason, u32), Decimal>,
+    // Dispute cases by the id of the deposit they are about.
+    #[serde(skip)]
+    cases: BTreeMap<u32, Case>,
+    // Interest accrued but not yet credited, to full precision.
+    #[serde(skip)]
+    accrued_interest: Decimal,
+    // Shared by every account, as it's the same for all of them.
+    #[serde(skip)]
+    fees: Rc<FeeSchedule>,
+    // Shared by every account, like the fees.
+    #[serde(skip)]
+    limits: Rc<Limits>,
+    // Withdrawals recent enough to count towards the limits.
     #[serde(skip)]
-    deposits: HashMap<u32, Transaction>,
+    recent_withdrawals: Withdrawals,
+    // Limits a withdrawal has taken the client near to, until the engine collects them.
+    #[serde(skip)]
+    warnings: Vec<Usage>,
+    // Recent activity, for the risk rules.
+    #[serde(skip)]
+    activity: Activity,
+    // Recent deposits, disputes and chargebacks, for the chargeback ratio.
+    #[serde(skip)]
+    tally: Tally,
+    // Postings made since the engine last collected them for the ledger.
+    #[serde(skip)]
+    postings: Vec<Posting>,
+    // What has happened to the account, if the engine is keeping track.
+    #[serde(skip)]
+    history: Vec<HistoryEntry>,
+}
+
+// The balances of an account at some point in time.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+// Decides what happens when a chargeback is for more than the available funds,
+// e.g when the disputed deposit has already been withdrawn.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegativeBalancePolicy {
+    // The chargeback is rejected, just like a withdrawal would be.
+    #[default]
+    Reject,
+    // The chargeback goes through and the account goes into debt. Nothing but
+    // chargebacks may ever drive available funds below zero.
+    AllowOnChargeback,
+}
+
+fn serialize_optional_with_fixed_digits<S>(
+    num: &Option<Decimal>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_with_fixed_digits(&num.unwrap_or_default(), serializer)
 }
 
 // This is here so that we can keep the output to 4 decimal places.
-fn serialize_with_fixed_digits<S>(num: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+pub fn serialize_with_fixed_digits<S>(num: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -34,6 +170,239 @@ impl Account {
             ..Default::default()
         }
     }
+    pub fn with_negative_balance_policy(mut self, policy: NegativeBalancePolicy) -> Self {
+        self.negative_balance_policy = policy;
+        self.update_totals();
+        self
+    }
+    // Gives the account a credit line, which also adds the overdraft column.
+    pub fn with_overdraft(mut self, overdraft: Overdraft) -> Self {
+        self.overdraft_limit = overdraft;
+        self.overdraft = Some(Decimal::ZERO);
+        self.update_totals();
+        self
+    }
+    pub fn with_windows(mut self, windows: Windows) -> Self {
+        self.windows = windows;
+        self
+    }
+    // Gives the account a rolling reserve, which also adds the reserved column.
+    pub fn with_reserve(mut self, reserve: Reserve) -> Self {
+        self.reserve = reserve;
+        self.reserved = Some(Decimal::ZERO);
+        self
+    }
+    pub fn with_fees(mut self, fees: Rc<FeeSchedule>) -> Self {
+        self.fees = fees;
+        self
+    }
+    pub fn with_limits(mut self, limits: Rc<Limits>) -> Self {
+        self.limits = limits;
+        self
+    }
+    pub fn get_client(&self) -> &u16 {
+        &self.client
+    }
+    pub fn get_available(&self) -> Decimal {
+        self.available
+    }
+    pub fn get_held(&self) -> Decimal {
+        self.held
+    }
+    pub fn balances(&self) -> Balances {
+        Balances {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+    pub fn get_accrued_interest(&self) -> Decimal {
+        self.accrued_interest
+    }
+    // Accrues days of interest at the given daily rate on the available funds,
+    // if there are any. Locked accounts earn nothing.
+    pub fn accrue_interest(&mut self, daily_rate: Decimal, days: u64) {
+        if !self.locked && self.available > Decimal::ZERO {
+            self.accrued_interest += self.available * daily_rate * Decimal::from(days);
+        }
+    }
+    pub fn get_history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+    pub fn record(&mut self, entry: HistoryEntry) {
+        self.history.push(entry);
+    }
+    pub fn get_activity(&self) -> &Activity {
+        &self.activity
+    }
+    // Takes note of an applied transaction for the risk rules, remembering
+    // activity for as long as the window.
+    pub fn observe(&mut self, transaction: &Transaction, now: Option<Timestamp>, window: u64) {
+        self.activity.observe(transaction, now, window);
+    }
+    pub fn get_tally(&self) -> &Tally {
+        &self.tally
+    }
+    // Counts an applied transaction towards the chargeback ratio.
+    pub fn tally(&mut self, transaction: &Transaction, now: Option<Timestamp>, window: u64) {
+        self.tally.observe(transaction, now, window);
+    }
+    // Hands over the postings made since the last call, for the ledger.
+    pub fn take_postings(&mut self) -> Vec<Posting> {
+        std::mem::take(&mut self.postings)
+    }
+    pub fn take_warnings(&mut self) -> Vec<Usage> {
+        std::mem::take(&mut self.warnings)
+    }
+    // Every change to available and held goes through here so that it is
+    // recorded as a balanced transfer between ledger accounts.
+    fn transfer(&mut self, from: LedgerAccount, to: LedgerAccount, amount: Decimal) {
+        for posting in transfer(from, to, amount) {
+            self.book(posting.account, posting.amount);
+            self.postings.push(posting);
+        }
+        self.update_totals();
+    }
+    // Adds amount to the balance of the given ledger account, if it is one of ours.
+    fn book(&mut self, account: LedgerAccount, amount: Decimal) {
+        match account {
+            LedgerAccount::ClientAvailable(client) if client == self.client => {
+                self.available += amount
+            }
+            LedgerAccount::ClientHeld(client) if client == self.client => self.held += amount,
+            _ => (),
+        }
+    }
+    fn checkpoint(&self, tx: u32) -> Checkpoint {
+        Checkpoint {
+            tx,
+            postings: self.postings.len(),
+            locked: self.locked,
+            holds: HOLD_REASONS.map(|reason| self.holds.get(&(reason, tx)).copied()),
+            reserve: self.reserves.get(&tx).copied(),
+        }
+    }
+    // Undoes everything done to the account since the checkpoint.
+    fn roll_back(&mut self, checkpoint: Checkpoint) {
+        for posting in self.postings.split_off(checkpoint.postings) {
+            self.book(posting.account, -posting.amount);
+        }
+        for (reason, amount) in HOLD_REASONS.into_iter().zip(checkpoint.holds) {
+            let key = (reason, checkpoint.tx);
+            if reason == HoldReason::Reserve {
+                self.add_reserved(
+                    amount.unwrap_or_default() - self.holds.get(&key).copied().unwrap_or_default(),
+                );
+            }
+            match amount {
+                Some(amount) => self.holds.insert(key, amount),
+                None => self.holds.remove(&key),
+            };
+        }
+        match checkpoint.reserve {
+            Some(release_at) => self.reserves.insert(checkpoint.tx, release_at),
+            None => self.reserves.remove(&checkpoint.tx),
+        };
+        self.locked = checkpoint.locked;
+        self.update_totals();
+    }
+    // Records a deposit (or withdrawal) in the store once the account has been
+    // changed for it, rolling those changes back should the store fail, so that
+    // money never moves for a transaction that is then rejected.
+    fn store(
+        &mut self,
+        deposits: &mut dyn DepositStore,
+        deposit: TimedTransaction,
+        checkpoint: Checkpoint,
+    ) -> Result<()> {
+        deposits
+            .insert(checkpoint.tx, deposit)
+            .inspect_err(|_| self.roll_back(checkpoint))
+    }
+    // Moves amount from available into the hold of the given reason and id.
+    fn hold(&mut self, reason: HoldReason, id: u32, amount: Decimal) {
+        self.hold_from(reason, id, amount, self.available_account());
+    }
+    // Moves amount from the given ledger account into the hold of the given reason and id.
+    fn hold_from(&mut self, reason: HoldReason, id: u32, amount: Decimal, from: LedgerAccount) {
+        self.transfer(from, self.held_account(), amount);
+        *self.holds.entry((reason, id)).or_default() += amount;
+        if reason == HoldReason::Reserve {
+            self.add_reserved(amount);
+        }
+    }
+    // Moves amount out of the hold of the given reason and id to the given
+    // ledger account, forgetting the hold once there is nothing left of it.
+    fn unhold(&mut self, reason: HoldReason, id: u32, amount: Decimal, to: LedgerAccount) {
+        self.transfer(self.held_account(), to, amount);
+        let hold = self.holds.entry((reason, id)).or_default();
+        *hold -= amount;
+        if hold.is_zero() {
+            self.holds.remove(&(reason, id));
+        }
+        if reason == HoldReason::Reserve {
+            self.add_reserved(-amount);
+        }
+    }
+    // Keeps the reserved column, if there is one, in line with the reserve holds.
+    fn add_reserved(&mut self, amount: Decimal) {
+        if let Some(reserved) = &mut self.reserved {
+            *reserved += amount;
+        }
+    }
+    // Every hold on the account, by reason and id.
+    pub fn holds(&self) -> impl Iterator<Item = HoldLine> + '_ {
+        self.holds.iter().map(|((reason, id), amount)| HoldLine {
+            client: self.client,
+            reason: *reason,
+            id: *id,
+            amount: *amount,
+        })
+    }
+    fn available_account(&self) -> LedgerAccount {
+        LedgerAccount::ClientAvailable(self.client)
+    }
+    fn held_account(&self) -> LedgerAccount {
+        LedgerAccount::ClientHeld(self.client)
+    }
+    // Keeps the derived fields (total, overdraft and debt) in line with available and held.
+    fn update_totals(&mut self) {
+        self.total = self.available + self.held;
+        let negative = if self.available < Decimal::ZERO {
+            -self.available
+        } else {
+            Decimal::ZERO
+        };
+        let overdraft = negative.min(self.overdraft_limit.limit);
+        self.overdraft = self.overdraft.map(|_| overdraft);
+        self.debt = (self.negative_balance_policy == NegativeBalancePolicy::AllowOnChargeback)
+            .then_some(negative - overdraft);
+    }
+    fn fee(&self, kind: &str, amount: Decimal) -> Decimal {
+        self.fees.fee(self.client, kind, amount)
+    }
+    fn charge_fee(&mut self, fee: Decimal) {
+        if !fee.is_zero() {
+            self.transfer(self.available_account(), LedgerAccount::FeeRevenue, fee);
+        }
+    }
+    // Any fee on a deposit is taken out of it, and the deposit is rejected if
+    // the fee would take the account below what it may go down to.
+    fn deposit_less_fee(&mut self, amount: Decimal) -> Result<()> {
+        let fee = self.fee("deposit", amount);
+        if self.available + amount - fee < -self.overdraft_limit.limit {
+            return Err(anyhow!(
+                "account {}: insufficient funds for a fee of {:.4} on a deposit of {:.4}",
+                self.client,
+                fee,
+                amount
+            ));
+        }
+        self.deposit(amount)?;
+        self.charge_fee(fee);
+        Ok(())
+    }
     // A deposit should increase available funds.
     // If the account has been "frozen" (i.e locked),
     // no deposits are allowed.
@@ -41,124 +410,753 @@ impl Account {
         if self.locked {
             return Err(anyhow!("account {} locked", self.client));
         }
-        self.available += amount;
-        self.total = self.available + self.held;
+        self.transfer(
+            LedgerAccount::ExternalSettlement,
+            self.available_account(),
+            amount,
+        );
         Ok(())
     }
     // A withdrawal should decrease available funds.
     // If there is insufficient funds or the account has been
     // "frozen" (i.e locked), no withdrawals are allowed.
+    // Clients with an overdraft may go down to -limit, paying
+    // any fee the overdraft carries on top of the withdrawal,
+    // as well as any fee from the fee schedule.
     fn withdraw(&mut self, amount: Decimal) -> Result<()> {
         if self.locked {
             return Err(anyhow!("account {} locked", self.client));
         }
-        if self.available < amount {
+        let fee = self
+            .overdraft_limit
+            .fee(self.available, self.available - amount)
+            + self.fee("withdrawal", amount);
+        if self.available - amount - fee < -self.overdraft_limit.limit {
             return Err(anyhow!(
                 "account {}: insufficient funds, want {:.4}, have {:.4}",
                 self.client,
+                amount + fee,
+                self.available + self.overdraft_limit.limit
+            ));
+        }
+        self.transfer(
+            self.available_account(),
+            LedgerAccount::ExternalSettlement,
+            amount,
+        );
+        self.charge_fee(fee);
+        Ok(())
+    }
+    // Counts a withdrawal, or capture, that went through towards the limits,
+    // along with any warnings it raised.
+    fn count_withdrawal(
+        &mut self,
+        now: Option<Timestamp>,
+        tx: u32,
+        amount: Decimal,
+        warnings: Vec<Usage>,
+    ) {
+        if let Some(window) = self.limits.longest_window() {
+            self.recent_withdrawals.push(now, tx, amount, window);
+        }
+        self.warnings.extend(warnings);
+    }
+    // Reversing a deposit takes the money back out of available funds, subject
+    // to the same checks as a withdrawal but without any overdraft fee. What was
+    // reserved from the deposit tx is cut down to the reserve on what is left of
+    // it, and what no longer needs to be reserved goes towards the reversal.
+    fn reverse_deposit(&mut self, tx: u32, amount: Decimal, left: Decimal) -> Result<()> {
+        if self.locked {
+            return Err(anyhow!("account {} locked", self.client));
+        }
+        let reserved = self
+            .holds
+            .get(&(HoldReason::Reserve, tx))
+            .copied()
+            .unwrap_or_default();
+        let released = (reserved - self.reserve.portion_of(left)).max(Decimal::ZERO);
+        if self.available + released - amount < -self.overdraft_limit.limit {
+            return Err(anyhow!(
+                "account {}: insufficient funds to reverse {:.4}, have {:.4}",
+                self.client,
                 amount,
-                self.available
+                self.available + released + self.overdraft_limit.limit
             ));
         }
-        self.available -= amount;
-        self.total = self.available + self.held;
+        if !released.is_zero() {
+            self.unhold(HoldReason::Reserve, tx, released, self.available_account());
+            if released == reserved {
+                self.reserves.remove(&tx);
+            }
+        }
+        self.transfer(
+            self.available_account(),
+            LedgerAccount::ExternalSettlement,
+            amount,
+        );
         Ok(())
     }
     // A dispute results in the disputed amount being held
     // which means the available funds should decrease by
     // the disputed amount and the held amount increase by
     // the same.
-    fn dispute(&mut self, amount: Decimal) -> Result<()> {
-        self.held += amount;
-        self.available -= amount;
-        self.total = self.available + self.held;
+    fn dispute(&mut self, tx: u32, amount: Decimal) -> Result<()> {
+        self.hold(HoldReason::Dispute, tx, amount);
         Ok(())
     }
     // Resolving a dispute results in reversing the dispute, i.e
     // the account should "revert" the dispute. We do so here by
-    // negating the input to dispute.
-    fn resolve(&mut self, amount: Decimal) -> Result<()> {
-        self.dispute(-amount)
+    // moving the amount back from held to available.
+    fn resolve(&mut self, tx: u32, amount: Decimal) -> Result<()> {
+        self.unhold(HoldReason::Dispute, tx, amount, self.available_account());
+        Ok(())
     }
     // A chargeback should result in the account being immediately
     // frozen (i.e locked), the dispute should be reversed and, importantly,
-    // a withdrawal of the disputed amount should happen.
-    fn chargeback(&mut self, amount: Decimal) -> Result<()> {
-        self.resolve(amount)?;
-        self.withdraw(amount)?;
+    // a withdrawal of the disputed amount should happen. Together that means
+    // the held funds leave the account.
+    // Whether that may leave the account in debt is decided by the negative
+    // balance policy. When it may not, the chargeback is subject to the same
+    // checks as a withdrawal and a rejected chargeback leaves the dispute as it was.
+    // That includes being rejected on a locked account. When it may, it goes
+    // through even then: the policy is there to record what the card network
+    // has already taken, which a lock on our side doesn't stop.
+    // Any chargeback fee is charged along with it, and counts towards the checks.
+    // So does, like for a withdrawal, any overdraft fee for the part of it that
+    // dips into a fee bearing credit line, unless the account may go into debt.
+    fn chargeback(&mut self, tx: u32, amount: Decimal) -> Result<()> {
+        let mut fee = self.fee("chargeback", amount);
+        if self.negative_balance_policy == NegativeBalancePolicy::Reject {
+            if self.locked {
+                return Err(anyhow!("account {} locked", self.client));
+            }
+            fee += self
+                .overdraft_limit
+                .fee(self.available + amount, self.available);
+            if self.available - fee < -self.overdraft_limit.limit {
+                return Err(anyhow!(
+                    "account {}: insufficient funds for chargeback of {:.4}, have {:.4}",
+                    self.client,
+                    amount,
+                    self.available + amount + self.overdraft_limit.limit
+                ));
+            }
+        }
+        self.unhold(
+            HoldReason::Dispute,
+            tx,
+            amount,
+            LedgerAccount::ChargebackLoss,
+        );
+        self.charge_fee(fee);
         self.lock()
     }
+    // An authorization moves funds from available to held, much like a dispute,
+    // but is subject to the same checks as a withdrawal since that's what it
+    // will eventually turn into. Nothing, or less than nothing, can't be
+    // authorized.
+    fn authorize(&mut self, tx: u32, amount: Decimal) -> Result<()> {
+        if self.locked {
+            return Err(anyhow!("account {} locked", self.client));
+        }
+        if amount <= Decimal::ZERO {
+            return Err(anyhow!(
+                "account {}: cannot authorize {:.4}",
+                self.client,
+                amount
+            ));
+        }
+        if self.available - amount < -self.overdraft_limit.limit {
+            return Err(anyhow!(
+                "account {}: insufficient funds to authorize {:.4}, have {:.4}",
+                self.client,
+                amount,
+                self.available + self.overdraft_limit.limit
+            ));
+        }
+        self.hold(HoldReason::Authorization, tx, amount);
+        Ok(())
+    }
+    // Capturing takes the funds out of held, turning them into a withdrawal.
+    // Like a withdrawal it carries any overdraft fee for the part of it that
+    // was authorized on the overdraft, along with any fee from the fee schedule,
+    // both paid from the available funds.
+    fn capture(&mut self, tx: u32, amount: Decimal) -> Result<()> {
+        if self.locked {
+            return Err(anyhow!("account {} locked", self.client));
+        }
+        let fee = self
+            .overdraft_limit
+            .fee(self.available + amount, self.available)
+            + self.fee("capture", amount);
+        if self.available - fee < -self.overdraft_limit.limit {
+            return Err(anyhow!(
+                "account {}: insufficient funds for a fee of {:.4} on a capture of {:.4}",
+                self.client,
+                fee,
+                amount
+            ));
+        }
+        self.unhold(
+            HoldReason::Authorization,
+            tx,
+            amount,
+            LedgerAccount::ExternalSettlement,
+        );
+        self.charge_fee(fee);
+        Ok(())
+    }
+    // Releasing returns held funds to available.
+    fn release(&mut self, tx: u32, amount: Decimal) -> Result<()> {
+        self.unhold(
+            HoldReason::Authorization,
+            tx,
+            amount,
+            self.available_account(),
+        );
+        Ok(())
+    }
+    // Legal holds may only hold what is available, as they can't take what isn't there.
+    fn hold_available(&mut self, reason: HoldReason, tx: u32, amount: Decimal) -> Result<()> {
+        if self.holds.contains_key(&(reason, tx)) {
+            return Err(anyhow!("{} hold {} already exists", reason, tx));
+        }
+        if amount <= Decimal::ZERO || amount > self.available {
+            return Err(anyhow!(
+                "account {}: cannot hold {:.4} with {:.4} available",
+                self.client,
+                amount,
+                self.available
+            ));
+        }
+        self.hold(reason, tx, amount);
+        Ok(())
+    }
+    // Releases all of a hold of the given reason and id to the available funds.
+    fn release_hold(&mut self, reason: HoldReason, tx: u32) -> Result<()> {
+        let amount = *self
+            .holds
+            .get(&(reason, tx))
+            .ok_or_else(|| anyhow!("nothing is on {} hold {}", reason, tx))?;
+        self.unhold(reason, tx, amount, self.available_account());
+        Ok(())
+    }
     fn lock(&mut self) -> Result<()> {
         self.locked = true;
         Ok(())
     }
+    // Every dispute case of the account, by deposit.
+    pub fn cases(&self) -> impl Iterator<Item = &Case> {
+        self.cases.values()
+    }
+    pub fn case(&self, tx: u32) -> Option<&Case> {
+        self.cases.get(&tx)
+    }
+    // Adds a note to the case of the deposit tx.
+    pub fn note_case(&mut self, tx: u32, now: Option<Timestamp>, text: String) -> Result<()> {
+        let case = self
+            .cases
+            .get_mut(&tx)
+            .ok_or_else(|| anyhow!("there is no case {} to note", tx))?;
+        case.note(now, text);
+        Ok(())
+    }
+    // Fails unless the case of the deposit tx may move on to the given stage.
+    // This is checked before anything else is done about a stage change.
+    fn check_case(&self, tx: u32, stage: Stage) -> Result<()> {
+        check_move(tx, self.cases.get(&tx).map(Case::stage), stage)
+    }
+    // Moves the case of the deposit tx on to the given stage, opening it for
+    // amount if there is none yet. The case is for what is disputed, which may be
+    // less than what was inquired about if part of the deposit was reversed since.
+    fn advance_case(
+        &mut self,
+        tx: u32,
+        amount: Decimal,
+        stage: Stage,
+        now: Option<Timestamp>,
+    ) -> Result<()> {
+        match self.cases.get_mut(&tx) {
+            Some(case) => {
+                case.advance(stage, now)?;
+                if stage == Stage::Dispute {
+                    case.amount = amount;
+                }
+                Ok(())
+            }
+            None => {
+                let case = Case::open(tx, self.client, amount, stage, now)?;
+                self.cases.insert(tx, case);
+                Ok(())
+            }
+        }
+    }
+    // The amount of the case of the deposit tx, once it may move on to the given stage.
+    fn case_amount(&self, tx: u32, stage: Stage) -> Result<Decimal> {
+        self.check_case(tx, stage)?;
+        self.cases
+            .get(&tx)
+            .map(|case| case.amount)
+            .ok_or_else(|| anyhow!("there is no case {}", tx))
+    }
+
+    // The ids of deposits whose reserves are due to be released.
+    pub fn due_reserves(&self, now: Option<Timestamp>) -> Vec<u32> {
+        let mut due: Vec<u32> = self
+            .reserves
+            .iter()
+            .filter(|(_, release_at)| release_at.is_some_and(|at| now >= Some(at)))
+            .map(|(tx, _)| *tx)
+            .collect();
+        due.sort_unstable();
+        due
+    }
 
-    pub fn apply_transaction(&mut self, transaction: Transaction) -> Result<()> {
+    // The ids of authorizations that have been around for longer than the
+    // authorization window allows and should be released.
+    pub fn expired_authorizations(&self, now: Option<Timestamp>) -> Vec<u32> {
+        let mut expired: Vec<u32> = self
+            .authorizations
+            .iter()
+            .filter(|(_, auth)| has_elapsed(auth.timestamp, now, self.windows.authorization))
+            .map(|(tx, _)| *tx)
+            .collect();
+        expired.sort_unstable();
+        expired
+    }
+
+    // Applies a transaction without knowing when it happened, meaning that no
+    // time windows apply to it.
+    #[cfg(test)]
+    pub fn apply_transaction(
+        &mut self,
+        transaction: Transaction,
+        deposits: &mut dyn DepositStore,
+    ) -> Result<()> {
+        self.apply_timed_transaction(
+            TimedTransaction {
+                transaction,
+                timestamp: None,
+            },
+            deposits,
+        )
+    }
+
+    // Deposits are tracked in the given store rather than by the account itself, see
+    // the store module.
+    pub fn apply_timed_transaction(
+        &mut self,
+        timed: TimedTransaction,
+        deposits: &mut dyn DepositStore,
+    ) -> Result<()> {
+        let TimedTransaction {
+            transaction,
+            timestamp: now,
+        } = timed;
         match transaction {
-            // Only deposits can be disputed, resolved or chargeback:ed so it is the only
-            // type of transaction being tracked in the deposits field (a HashMap).
+            // Only deposits can be disputed, resolved or chargeback:ed so they are tracked
+            // in the deposit store, along with withdrawals for reversals and refunds.
+            // Only deposits that went through are stored, so a rejected one can't be disputed.
             Transaction::Deposit { tx, amount, .. } => {
-                self.deposits.insert(tx, transaction);
-                self.deposit(amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?)
+                let amount = amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?;
+                let release_at = self.reserve.release_at(now)?;
+                let checkpoint = self.checkpoint(tx);
+                self.deposit_less_fee(amount)?;
+                self.store(
+                    deposits,
+                    TimedTransaction {
+                        transaction,
+                        timestamp: now,
+                    },
+                    checkpoint,
+                )?;
+                // Part of the deposit may be held as a rolling reserve.
+                let reserved = self.reserve.portion_of(amount);
+                if !reserved.is_zero() {
+                    self.hold(HoldReason::Reserve, tx, reserved);
+                    self.reserves.insert(tx, release_at);
+                }
+                Ok(())
+            }
+            // Returns what was reserved from a deposit to the available funds.
+            // Reserves of deposits made at a known time are released by the engine once
+            // due and never before, or there would be no point to the reserve period.
+            // Only reserves of deposits made at an unknown time are released by hand.
+            Transaction::ReserveRelease { tx, .. } => {
+                let release_at = *self
+                    .reserves
+                    .get(&tx)
+                    .ok_or_else(|| anyhow!("nothing is reserved from deposit {}", tx))?;
+                if let Some(release_at) = release_at.filter(|at| now < Some(*at)) {
+                    return Err(anyhow!(
+                        "reserve of deposit {} is held until {}",
+                        tx,
+                        release_at
+                    ));
+                }
+                self.reserves.remove(&tx);
+                let reserved = self
+                    .holds
+                    .get(&(HoldReason::Reserve, tx))
+                    .copied()
+                    .unwrap_or_default();
+                self.unhold(HoldReason::Reserve, tx, reserved, self.available_account());
+                Ok(())
+            }
+            Transaction::LegalHold { tx, amount, .. } => self.hold_available(
+                HoldReason::Legal,
+                tx,
+                amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?,
+            ),
+            Transaction::LegalRelease { tx, .. } => self.release_hold(HoldReason::Legal, tx),
+            // Transactions held for review are kept by the engine, which lets them through
+            // on their release, so any release reaching the account has nothing to release.
+            Transaction::ReviewRelease { tx, .. } => {
+                Err(anyhow!("nothing is held for review under tx {}", tx))
             }
+            Transaction::Lock { .. } => self.lock(),
+            // Withdrawals that went through are stored too, as they may be reversed or refunded.
+            // Withdrawals are checked against the limits first and only count towards
+            // them once they go through.
             Transaction::Withdrawal { tx, amount, .. } => {
-                self.withdraw(amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?)
+                let amount = amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?;
+                let warnings =
+                    self.limits
+                        .check(self.client, &self.recent_withdrawals, now, amount)?;
+                let checkpoint = self.checkpoint(tx);
+                self.withdraw(amount)?;
+                self.store(
+                    deposits,
+                    TimedTransaction {
+                        transaction,
+                        timestamp: now,
+                    },
+                    checkpoint,
+                )?;
+                self.count_withdrawal(now, tx, amount, warnings);
+                Ok(())
+            }
+            // Reversals refer to the deposit or withdrawal they void, in full or (given an
+            // amount) in part. Reversing a deposit takes the money back out, reversing a
+            // withdrawal puts it back in. What is left of the original is stored, so that
+            // a partially reversed deposit can only be disputed for what is left.
+            Transaction::Reversal { tx, amount, .. } => {
+                let mut original = deposits
+                    .get(tx)?
+                    .ok_or_else(|| anyhow!("reversal refers to non-existent transaction {}", tx))?;
+                let is_deposit = matches!(
+                    original.transaction,
+                    Transaction::Deposit { .. } | Transaction::Resolve { .. }
+                );
+                let reversed = original.transaction.reverse(self.client, amount)?;
+                let left = original.transaction.get_amount().unwrap_or_default();
+                let checkpoint = self.checkpoint(tx);
+                if is_deposit {
+                    self.reverse_deposit(tx, reversed, left)?;
+                } else {
+                    self.deposit(reversed)?;
+                }
+                let timestamp = original.timestamp;
+                self.store(deposits, original, checkpoint)?;
+                if !is_deposit {
+                    self.recent_withdrawals
+                        .take_back(timestamp, tx, reversed, left);
+                }
+                Ok(())
+            }
+            // Interest is credited out of what has been accrued, never more.
+            Transaction::Interest { tx, amount, .. } => {
+                let amount = amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?;
+                if amount > self.accrued_interest {
+                    return Err(anyhow!(
+                        "account {}: cannot credit {:.4} of interest with {:.4} accrued",
+                        self.client,
+                        amount,
+                        self.accrued_interest
+                    ));
+                }
+                self.accrued_interest -= amount;
+                self.transfer(
+                    LedgerAccount::InterestExpense,
+                    self.available_account(),
+                    amount,
+                );
+                Ok(())
+            }
+            // Refunds refer to the withdrawal they credit back, never for more than is
+            // left of it after earlier refunds and reversals.
+            Transaction::Refund { tx, amount, .. } => {
+                let mut withdrawal = deposits
+                    .get(tx)?
+                    .ok_or_else(|| anyhow!("refund refers to non-existent withdrawal {}", tx))?;
+                let refunded = withdrawal.transaction.refund(self.client, amount)?;
+                let left = withdrawal.transaction.get_amount().unwrap_or_default();
+                let timestamp = withdrawal.timestamp;
+                let checkpoint = self.checkpoint(tx);
+                self.deposit(refunded)?;
+                self.store(deposits, withdrawal, checkpoint)?;
+                self.recent_withdrawals
+                    .take_back(timestamp, tx, refunded, left);
+                Ok(())
+            }
+            // Credit lines are granted by the operator, never by the transactions
+            // themselves, or a client could grant itself as much credit as it liked.
+            Transaction::Overdraft { tx, .. } => Err(anyhow!(
+                "transaction {}: overdraft limits can only be set with --overdraft-limits",
+                tx
+            )),
+            // Authorizations are tracked by their id in the authorizations field (a HashMap)
+            // until they have been fully captured or released.
+            Transaction::Authorize { tx, amount, .. } => {
+                if self.authorizations.contains_key(&tx) {
+                    return Err(anyhow!("authorization {} already exists", tx));
+                }
+                self.authorize(
+                    tx,
+                    amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?,
+                )?;
+                self.authorizations.insert(
+                    tx,
+                    TimedTransaction {
+                        transaction,
+                        timestamp: now,
+                    },
+                );
+                Ok(())
+            }
+            // Captures refer to the authorization they finalize. Without an amount whatever
+            // the authorization still holds is captured. Being withdrawals, they are checked
+            // against the limits and count towards them like any other.
+            Transaction::Capture { tx, amount, .. } => {
+                let authorization = self.authorizations.get(&tx).ok_or_else(|| {
+                    anyhow!("capture refers to non-existent authorization {}", tx)
+                })?;
+                let mut captured = authorization.clone();
+                let amount = match amount {
+                    Some(amount) => amount,
+                    None => authorization
+                        .transaction
+                        .get_amount()
+                        .ok_or_else(|| anyhow!("authorization {} missing amount", tx))?,
+                };
+                let remaining = captured.transaction.capture(self.client, amount)?;
+                let warnings =
+                    self.limits
+                        .check(self.client, &self.recent_withdrawals, now, amount)?;
+                self.capture(tx, amount)?;
+                self.count_withdrawal(now, tx, amount, warnings);
+                if remaining.is_zero() {
+                    self.authorizations.remove(&tx);
+                } else {
+                    self.authorizations.insert(tx, captured);
+                }
+                Ok(())
+            }
+            // Releases refer to the authorization they release, returning what it still holds.
+            Transaction::Release { tx, .. } => {
+                let authorization = self.authorizations.get(&tx).ok_or_else(|| {
+                    anyhow!("release refers to non-existent authorization {}", tx)
+                })?;
+                if *authorization.transaction.get_client() != self.client {
+                    return Err(anyhow!(
+                        "cannot release authorization {} belonging to client {} as client {}",
+                        tx,
+                        authorization.transaction.get_client(),
+                        self.client
+                    ));
+                }
+                let amount = authorization
+                    .transaction
+                    .get_amount()
+                    .ok_or_else(|| anyhow!("authorization {} missing amount", tx))?;
+                self.release(tx, amount)?;
+                self.authorizations.remove(&tx);
+                Ok(())
             }
             // Disputes don't have their own unique tx id but rather contain the tx id
-            // they refer to. We fetch a transaction from the deposits hashmap via that id
+            // they refer to. We fetch a transaction from the deposit store via that id
             // and dispute it. See the private dispute method.
             // We also use the dispute method on the transaction itself which will turn
             // the deposit into a dispute.
+            // Disputes arriving after the dispute window has closed are rejected.
             Transaction::Dispute { tx, .. } => {
-                let transaction = self.deposits.get_mut(&tx).ok_or_else(|| {
+                let mut deposit = deposits.get(tx)?.ok_or_else(|| {
                     anyhow!("dispute refers to non-existent deposit transaction {}", tx)
                 })?;
-                let amount = transaction
+                if has_elapsed(deposit.timestamp, now, self.windows.dispute) {
+                    return Err(anyhow!(
+                        "dispute window for deposit transaction {} has closed",
+                        tx
+                    ));
+                }
+                let amount = deposit
+                    .transaction
                     .get_amount()
                     .ok_or_else(|| anyhow!("transaction {} missing amount", tx))?;
-                transaction.dispute(self.client)?;
-                self.dispute(amount)
+                deposit.transaction.dispute(self.client)?;
+                self.check_case(tx, Stage::Dispute)?;
+                let checkpoint = self.checkpoint(tx);
+                self.dispute(tx, amount)?;
+                self.store(deposits, deposit, checkpoint)?;
+                self.advance_case(tx, amount, Stage::Dispute, now)
             }
             // Resolves don't have their own unique tx id but rather contain the tx id
-            // they refer to. We fetch a transaction from the deposits hashmap via that id
+            // they refer to. We fetch a transaction from the deposit store via that id
             // and resolve it. Please note that that deposit should previously have turned
             // into a dispute. If not, this will fail.
+            // Resolving a case that is at the inquiry stage closes it without anything
+            // having been held, and resolving one at representment returns what it holds.
             Transaction::Resolve { tx, .. } => {
-                let transaction = self.deposits.get_mut(&tx).ok_or_else(|| {
+                match self.cases.get(&tx).map(|case| (case.stage(), case.amount)) {
+                    Some((Stage::Inquiry, amount)) => {
+                        return self.advance_case(tx, amount, Stage::Resolved, now)
+                    }
+                    Some((Stage::Representment, amount)) => {
+                        self.resolve(tx, amount)?;
+                        return self.advance_case(tx, amount, Stage::Resolved, now);
+                    }
+                    _ => (),
+                }
+                let mut deposit = deposits.get(tx)?.ok_or_else(|| {
                     anyhow!("resolve refers to non-existent dispute transaction {}", tx)
                 })?;
-                let amount = transaction
+                let amount = deposit
+                    .transaction
                     .get_amount()
                     .ok_or_else(|| anyhow!("transaction missing amount"))?;
-                transaction.resolve(self.client)?;
-                self.resolve(amount)
+                deposit.transaction.resolve(self.client)?;
+                self.check_case(tx, Stage::Resolved)?;
+                let checkpoint = self.checkpoint(tx);
+                self.resolve(tx, amount)?;
+                self.store(deposits, deposit, checkpoint)?;
+                self.advance_case(tx, amount, Stage::Resolved, now)
             }
             // Chargebacks don't have their own unique tx id but rather contain the tx id
-            // they refer to. We fetch a transaction from the deposits hashmap via that id
+            // they refer to. We fetch a transaction from the deposit store via that id
             // and chargeback it. Please note that that deposit should previously have turned
             // into a dispute. If not (i.e it is not a dispute), this will fail.
+            // As a chargeback may be rejected by the negative balance policy, the stored
+            // transaction is only updated once the chargeback has gone through.
             Transaction::Chargeback { tx, .. } => {
-                let transaction = self.deposits.get_mut(&tx).ok_or_else(|| {
+                let mut deposit = deposits.get(tx)?.ok_or_else(|| {
                     anyhow!(
                         "chargeback refers to non-existent dispute transaction {}",
                         tx
                     )
                 })?;
-                let amount = transaction
+                let amount = deposit
+                    .transaction
                     .get_amount()
                     .ok_or_else(|| anyhow!("transaction missing amount"))?;
-                transaction.chargeback(self.client)?;
-                self.chargeback(amount)
+                deposit.transaction.chargeback(self.client)?;
+                self.check_case(tx, Stage::Chargeback)?;
+                let checkpoint = self.checkpoint(tx);
+                self.chargeback(tx, amount)?;
+                self.store(deposits, deposit, checkpoint)?;
+                self.advance_case(tx, amount, Stage::Chargeback, now)
+            }
+            // Inquiries open a case for a deposit of the client, within the dispute
+            // window, but hold nothing until the case turns into a dispute.
+            Transaction::Inquiry { tx, .. } => {
+                let deposit = deposits.get(tx)?.ok_or_else(|| {
+                    anyhow!("inquiry refers to non-existent deposit transaction {}", tx)
+                })?;
+                match deposit.transaction {
+                    Transaction::Deposit { client, .. } if client == self.client => (),
+                    _ => {
+                        return Err(anyhow!(
+                            "only deposits of client {} can be inquired about but {} is not one",
+                            self.client,
+                            deposit.transaction
+                        ))
+                    }
+                }
+                if has_elapsed(deposit.timestamp, now, self.windows.dispute) {
+                    return Err(anyhow!(
+                        "dispute window for deposit transaction {} has closed",
+                        tx
+                    ));
+                }
+                let amount = deposit
+                    .transaction
+                    .get_amount()
+                    .ok_or_else(|| anyhow!("transaction {} missing amount", tx))?;
+                self.advance_case(tx, amount, Stage::Inquiry, now)
             }
+            // The later stages only concern the case, as the stored deposit stays charged
+            // back. Representment holds what was charged back again and pre-arbitration
+            // charges it back once more, while arbitration awards some of it, or none, to
+            // the client for good.
+            Transaction::Representment { tx, .. } => {
+                let amount = self.case_amount(tx, Stage::Representment)?;
+                self.hold_from(
+                    HoldReason::Dispute,
+                    tx,
+                    amount,
+                    LedgerAccount::ChargebackLoss,
+                );
+                self.advance_case(tx, amount, Stage::Representment, now)
+            }
+            Transaction::PreArbitration { tx, .. } => {
+                let amount = self.case_amount(tx, Stage::PreArbitration)?;
+                self.unhold(
+                    HoldReason::Dispute,
+                    tx,
+                    amount,
+                    LedgerAccount::ChargebackLoss,
+                );
+                self.advance_case(tx, amount, Stage::PreArbitration, now)
+            }
+            Transaction::Arbitration {
+                tx,
+                amount: awarded,
+                ..
+            } => {
+                let amount = self.case_amount(tx, Stage::Arbitration)?;
+                let awarded = awarded.unwrap_or_default();
+                if awarded < Decimal::ZERO || awarded > amount {
+                    return Err(anyhow!(
+                        "case {}: cannot award {:.4} of {:.4} in arbitration",
+                        tx,
+                        awarded,
+                        amount
+                    ));
+                }
+                if !awarded.is_zero() {
+                    self.transfer(
+                        LedgerAccount::ChargebackLoss,
+                        self.available_account(),
+                        awarded,
+                    );
+                }
+                self.advance_case(tx, amount, Stage::Arbitration, now)
+            }
+            // Notes change nothing but the case, which has to exist. Their text is added
+            // to it once they have gone through, see note_case.
+            Transaction::Note { tx, .. } => self
+                .cases
+                .contains_key(&tx)
+                .then_some(())
+                .ok_or_else(|| anyhow!("there is no case {} to note", tx)),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Account;
+    use super::{Account, NegativeBalancePolicy};
+    use crate::clock::{Windows, DAY};
+    use crate::fee::{FeeRule, FeeSchedule};
+    use crate::hold::HoldReason;
+    use crate::ledger::LedgerAccount;
+    use crate::overdraft::{Overdraft, OverdraftMode};
+    use crate::reserve::Reserve;
+    use crate::store::MemoryStore;
+    use crate::transaction::TimedTransaction;
     use crate::Transaction;
     use anyhow::Result;
+    use rust_decimal::Decimal;
+    use std::rc::Rc;
 
     #[test]
     fn a_new_account_is_empty() -> Result<()> {
@@ -172,11 +1170,15 @@ mod tests {
     #[test]
     fn a_deposit_transaction_deposits_money_in_the_account_it_is_applied_to() -> Result<()> {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(50.into()),
-            client: 1,
-            tx: 1,
-        })?;
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(50.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.available, account.total);
         assert_eq!(account.held, 0.into());
         assert_eq!(account.available, 50.into());
@@ -186,16 +1188,23 @@ mod tests {
     #[test]
     fn a_withdrawal_transaction_withdraws_money_from_the_account_it_is_applied_to() -> Result<()> {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(100.into()),
-            client: 1,
-            tx: 1,
-        })?;
-        account.apply_transaction(Transaction::Withdrawal {
-            amount: Some(50.into()),
-            client: 1,
-            tx: 2,
-        })?;
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Withdrawal {
+                amount: Some(50.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.available, account.total);
         assert_eq!(account.held, 0.into());
         assert_eq!(account.available, 50.into());
@@ -206,17 +1215,24 @@ mod tests {
     fn a_withdrawal_transaction_fails_silently_when_there_is_insufficient_funds_in_the_account_it_is_applied_to(
     ) -> Result<()> {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(100.into()),
-            client: 1,
-            tx: 1,
-        })?;
-        assert!(account
-            .apply_transaction(Transaction::Withdrawal {
-                amount: Some(101.into()),
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
                 client: 1,
-                tx: 2,
-            })
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert!(account
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    amount: Some(101.into()),
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
             .is_err(),);
         assert_eq!(account.available, account.total);
         assert_eq!(account.held, 0.into());
@@ -228,18 +1244,25 @@ mod tests {
     fn a_withdrawal_transaction_fails_silently_when_the_account_it_is_applied_to_is_locked(
     ) -> Result<()> {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(100.into()),
-            client: 1,
-            tx: 1,
-        })?;
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
         account.lock()?;
         assert!(account
-            .apply_transaction(Transaction::Withdrawal {
-                amount: Some(50.into()),
-                client: 1,
-                tx: 2,
-            })
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    amount: Some(50.into()),
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
             .is_err());
         assert_eq!(account.available, account.total);
         assert_eq!(account.held, 0.into());
@@ -251,24 +1274,34 @@ mod tests {
     fn a_dispute_transaction_holds_the_given_amount_in_the_account_it_is_applied_to() -> Result<()>
     {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(70.into()),
-            client: 1,
-            tx: 1,
-        })?;
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(30.into()),
-            client: 1,
-            tx: 2,
-        })?;
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(70.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(30.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.available, account.total);
         assert_eq!(account.held, 0.into());
         assert_eq!(account.available, 100.into());
-        account.apply_transaction(Transaction::Dispute {
-            amount: None,
-            client: 1,
-            tx: 2,
-        })?;
+        account.apply_transaction(
+            Transaction::Dispute {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.held, 30.into());
         assert_eq!(account.available, 70.into());
         assert_eq!(account.total, account.held + account.available);
@@ -279,32 +1312,45 @@ mod tests {
     fn a_resolve_transaction_unholds_the_given_amount_in_the_account_it_is_applied_to() -> Result<()>
     {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(100.into()),
-            client: 1,
-            tx: 1,
-        })?;
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(30.into()),
-            client: 1,
-            tx: 2,
-        })?;
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(30.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.held, 0.into());
         assert_eq!(account.available, 130.into());
         assert_eq!(account.total, account.held + account.available);
-        account.apply_transaction(Transaction::Dispute {
-            amount: None,
-            client: 1,
-            tx: 1,
-        })?;
+        account.apply_transaction(
+            Transaction::Dispute {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.held, 100.into());
         assert_eq!(account.available, 30.into());
         assert_eq!(account.total, account.held + account.available);
-        account.apply_transaction(Transaction::Resolve {
-            amount: None,
-            client: 1,
-            tx: 1,
-        })?;
+        account.apply_transaction(
+            Transaction::Resolve {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.held, 0.into());
         assert_eq!(account.available, 130.into());
         assert_eq!(account.total, account.held + account.available);
@@ -315,38 +1361,1041 @@ mod tests {
     fn a_chargeback_transaction_withdraws_amount_and_freezes_the_account_it_is_applied_to(
     ) -> Result<()> {
         let mut account = Account::new(1);
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(100.into()),
-            client: 1,
-            tx: 1,
-        })?;
-        account.apply_transaction(Transaction::Deposit {
-            amount: Some(20.into()),
-            client: 1,
-            tx: 2,
-        })?;
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(20.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.available, account.total);
         assert_eq!(account.held, 0.into());
         assert_eq!(account.available, 120.into());
         assert!(!account.locked);
-        account.apply_transaction(Transaction::Dispute {
-            amount: None,
-            client: 1,
-            tx: 1,
-        })?;
+        account.apply_transaction(
+            Transaction::Dispute {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
         assert_eq!(account.held, 100.into());
         assert_eq!(account.available, 20.into());
         assert_eq!(account.total, account.available + account.held);
         assert!(!account.locked);
-        account.apply_transaction(Transaction::Chargeback {
+        account.apply_transaction(
+            Transaction::Chargeback {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, account.total);
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.available, 20.into());
+        assert!(account.locked);
+        Ok(())
+    }
+
+    fn overdrawn_dispute(policy: NegativeBalancePolicy) -> Result<(Account, MemoryStore)> {
+        let mut account = Account::new(1).with_negative_balance_policy(policy);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Withdrawal {
+                amount: Some(80.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Dispute {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        Ok((account, deposits))
+    }
+
+    #[test]
+    fn a_chargeback_exceeding_available_funds_is_rejected_by_default() -> Result<()> {
+        let (mut account, mut deposits) = overdrawn_dispute(NegativeBalancePolicy::Reject)?;
+        assert!(account
+            .apply_transaction(
+                Transaction::Chargeback {
+                    amount: None,
+                    client: 1,
+                    tx: 1,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert_eq!(account.available, (-80).into());
+        assert_eq!(account.held, 100.into());
+        assert_eq!(account.total, 20.into());
+        assert!(!account.locked);
+        // The dispute is still open and can be resolved.
+        account.apply_transaction(
+            Transaction::Resolve {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 20.into());
+        Ok(())
+    }
+
+    #[test]
+    fn a_chargeback_exceeding_available_funds_puts_the_account_in_debt_when_allowed() -> Result<()>
+    {
+        let (mut account, mut deposits) =
+            overdrawn_dispute(NegativeBalancePolicy::AllowOnChargeback)?;
+        account.apply_transaction(
+            Transaction::Chargeback {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, (-80).into());
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, (-80).into());
+        assert_eq!(account.debt, Some(80.into()));
+        assert!(account.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn optional_columns_are_only_written_when_configured() -> Result<()> {
+        let header = |account: Account| -> Result<String> {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.serialize(account)?;
+            let output = String::from_utf8(writer.into_inner()?)?;
+            Ok(output.lines().next().unwrap_or_default().to_string())
+        };
+        assert_eq!(
+            header(Account::new(1))?,
+            "client,available,held,total,locked"
+        );
+        assert_eq!(
+            header(
+                Account::new(1)
+                    .with_negative_balance_policy(NegativeBalancePolicy::AllowOnChargeback)
+            )?,
+            "client,available,held,total,locked,debt"
+        );
+        assert_eq!(
+            header(Account::new(1).with_overdraft(Overdraft::default()))?,
+            "client,available,held,total,locked,overdraft"
+        );
+        assert_eq!(
+            header(Account::new(1).with_reserve(Reserve::default()))?,
+            "client,available,held,total,locked,reserved"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn only_chargebacks_may_put_an_account_in_debt() -> Result<()> {
+        let mut account =
+            Account::new(1).with_negative_balance_policy(NegativeBalancePolicy::AllowOnChargeback);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert!(account
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    amount: Some(11.into()),
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert_eq!(account.available, 10.into());
+        assert_eq!(account.debt, Some(0.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn a_withdrawal_may_use_the_overdraft_down_to_the_limit() -> Result<()> {
+        let mut account = Account::new(1).with_overdraft(Overdraft {
+            limit: 50.into(),
+            mode: OverdraftMode::InterestFree,
+        });
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Withdrawal {
+                amount: Some(40.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, (-30).into());
+        assert_eq!(account.overdraft, Some(30.into()));
+        assert_eq!(account.debt, None);
+        assert!(account
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    amount: Some(21.into()),
+                    client: 1,
+                    tx: 3,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert_eq!(account.available, (-30).into());
+        Ok(())
+    }
+
+    #[test]
+    fn a_fee_bearing_overdraft_charges_the_fee_with_the_withdrawal() -> Result<()> {
+        let mut account = Account::new(1).with_overdraft(Overdraft {
+            limit: 50.into(),
+            mode: OverdraftMode::FeeBearing {
+                rate: Decimal::new(1, 1),
+            },
+        });
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Withdrawal {
+                amount: Some(30.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, (-22).into());
+        assert_eq!(account.overdraft, Some(22.into()));
+        // 26 + 2.6 in fees would take the account past its limit.
+        assert!(account
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    amount: Some(26.into()),
+                    client: 1,
+                    tx: 3,
+                },
+                &mut deposits
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn a_fee_bearing_overdraft_charges_the_fee_with_the_capture() -> Result<()> {
+        let mut account = Account::new(1).with_overdraft(Overdraft {
+            limit: 50.into(),
+            mode: OverdraftMode::FeeBearing {
+                rate: Decimal::new(1, 1),
+            },
+        });
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Authorize {
+                amount: Some(30.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Capture {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        // The same as withdrawing the 30 right away.
+        assert_eq!(account.available, (-22).into());
+        assert_eq!(account.overdraft, Some(22.into()));
+        assert_eq!(account.held, 0.into());
+        Ok(())
+    }
+
+    #[test]
+    fn an_overdraft_transaction_cannot_grant_credit() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        assert!(account
+            .apply_transaction(
+                Transaction::Overdraft {
+                    amount: Some(100.into()),
+                    client: 1,
+                    tx: 1,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert!(account
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    amount: Some(60.into()),
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert_eq!(account.available, 0.into());
+        Ok(())
+    }
+
+    #[test]
+    fn a_chargeback_beyond_the_overdraft_limit_is_reported_as_debt() -> Result<()> {
+        let (account, mut deposits) = overdrawn_dispute(NegativeBalancePolicy::AllowOnChargeback)?;
+        let mut account = account.with_overdraft(Overdraft {
+            limit: 50.into(),
+            mode: OverdraftMode::InterestFree,
+        });
+        account.apply_transaction(
+            Transaction::Chargeback {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, (-80).into());
+        assert_eq!(account.overdraft, Some(50.into()));
+        assert_eq!(account.debt, Some(30.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn a_chargeback_into_a_fee_bearing_overdraft_charges_the_fee() -> Result<()> {
+        let (account, mut deposits) = overdrawn_dispute(NegativeBalancePolicy::Reject)?;
+        let mut account = account.with_overdraft(Overdraft {
+            limit: 100.into(),
+            mode: OverdraftMode::FeeBearing {
+                rate: Decimal::new(1, 1),
+            },
+        });
+        account.apply_transaction(
+            Transaction::Chargeback {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        // 80 of the 100 charged back dip into the credit line.
+        assert_eq!(account.available, (-88).into());
+        assert_eq!(account.overdraft, Some(88.into()));
+        assert_eq!(account.held, 0.into());
+        Ok(())
+    }
+
+    #[test]
+    fn an_authorization_holds_funds_until_it_is_captured() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Authorize {
+                amount: Some(60.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 40.into());
+        assert_eq!(account.held, 60.into());
+        account.apply_transaction(
+            Transaction::Capture {
+                amount: Some(25.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 40.into());
+        assert_eq!(account.held, 35.into());
+        assert_eq!(account.total, 75.into());
+        account.apply_transaction(
+            Transaction::Capture {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, 40.into());
+        assert!(account.authorizations.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn releasing_an_authorization_returns_what_it_still_holds() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(100.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Authorize {
+                amount: Some(60.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Capture {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Release {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 90.into());
+        assert_eq!(account.held, 0.into());
+        assert!(account
+            .apply_transaction(
+                Transaction::Capture {
+                    amount: None,
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn an_authorization_needs_sufficient_funds() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert!(account
+            .apply_transaction(
+                Transaction::Authorize {
+                    amount: Some(11.into()),
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert!(account
+            .apply_transaction(
+                Transaction::Capture {
+                    amount: Some(1.into()),
+                    client: 1,
+                    tx: 2,
+                },
+                &mut deposits
+            )
+            .is_err());
+        assert_eq!(account.available, 10.into());
+        assert_eq!(account.held, 0.into());
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_or_less_can_be_authorized_or_captured() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        for amount in [0, -5] {
+            assert!(account
+                .apply_transaction(
+                    Transaction::Authorize {
+                        amount: Some(amount.into()),
+                        client: 1,
+                        tx: 2,
+                    },
+                    &mut deposits
+                )
+                .is_err());
+        }
+        account.apply_transaction(
+            Transaction::Authorize {
+                amount: Some(10.into()),
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        for amount in [0, -5] {
+            assert!(account
+                .apply_transaction(
+                    Transaction::Capture {
+                        amount: Some(amount.into()),
+                        client: 1,
+                        tx: 2,
+                    },
+                    &mut deposits
+                )
+                .is_err());
+        }
+        assert_eq!(account.held, 10.into());
+        account.apply_transaction(
+            Transaction::Release {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 10.into());
+        Ok(())
+    }
+
+    #[test]
+    fn a_reversal_releases_what_no_longer_needs_to_be_reserved() -> Result<()> {
+        let mut account = Account::new(1).with_reserve(Reserve {
+            percentage: 10.into(),
+            days: 90,
+        });
+        let mut deposits = MemoryStore::default();
+        for tx in [1, 2] {
+            account.apply_transaction(
+                Transaction::Deposit {
+                    amount: Some(100.into()),
+                    client: 1,
+                    tx,
+                },
+                &mut deposits,
+            )?;
+        }
+        assert_eq!(account.reserved, Some(20.into()));
+        account.apply_transaction(
+            Transaction::Reversal {
+                amount: Some(50.into()),
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.reserved, Some(15.into()));
+        assert_eq!(account.available, 135.into());
+        account.apply_transaction(
+            Transaction::Reversal {
+                amount: None,
+                client: 1,
+                tx: 1,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.reserved, Some(10.into()));
+        assert_eq!(account.available, 90.into());
+        assert!(!account.reserves.contains_key(&1));
+        // Without a known time the other reserve is only released by hand.
+        assert!(account.due_reserves(None).is_empty());
+        account.apply_transaction(
+            Transaction::ReserveRelease {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.reserved, Some(0.into()));
+        assert_eq!(account.held, 0.into());
+        account.apply_transaction(
+            Transaction::Reversal {
+                amount: None,
+                client: 1,
+                tx: 2,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.total, 0.into());
+        Ok(())
+    }
+
+    #[test]
+    fn a_dated_reserve_cannot_be_released_before_it_is_due() -> Result<()> {
+        let mut account = Account::new(1).with_reserve(Reserve {
+            percentage: 10.into(),
+            days: 2,
+        });
+        let mut deposits = MemoryStore::default();
+        account.apply_timed_transaction(
+            at(
+                0,
+                Transaction::Deposit {
+                    amount: Some(100.into()),
+                    client: 1,
+                    tx: 1,
+                },
+            ),
+            &mut deposits,
+        )?;
+        let release = || Transaction::ReserveRelease {
             amount: None,
             client: 1,
             tx: 1,
-        })?;
-        assert_eq!(account.available, account.total);
+        };
+        assert!(account
+            .apply_timed_transaction(at(DAY, release()), &mut deposits)
+            .is_err());
+        assert!(account.apply_transaction(release(), &mut deposits).is_err());
+        assert_eq!(account.reserved, Some(10.into()));
+        account.apply_timed_transaction(at(2 * DAY, release()), &mut deposits)?;
+        assert_eq!(account.reserved, Some(0.into()));
+        assert_eq!(account.available, 100.into());
+        Ok(())
+    }
+
+    fn at(timestamp: u64, transaction: Transaction) -> TimedTransaction {
+        TimedTransaction {
+            transaction,
+            timestamp: Some(timestamp),
+        }
+    }
+
+    #[test]
+    fn a_dispute_after_the_dispute_window_has_closed_is_rejected() -> Result<()> {
+        let mut account = Account::new(1).with_windows(Windows {
+            dispute: Some(120 * DAY),
+            ..Default::default()
+        });
+        let mut deposits = MemoryStore::default();
+        account.apply_timed_transaction(
+            at(
+                0,
+                Transaction::Deposit {
+                    amount: Some(10.into()),
+                    client: 1,
+                    tx: 1,
+                },
+            ),
+            &mut deposits,
+        )?;
+        account.apply_timed_transaction(
+            at(
+                0,
+                Transaction::Deposit {
+                    amount: Some(20.into()),
+                    client: 1,
+                    tx: 2,
+                },
+            ),
+            &mut deposits,
+        )?;
+        account.apply_timed_transaction(
+            at(
+                120 * DAY,
+                Transaction::Dispute {
+                    amount: None,
+                    client: 1,
+                    tx: 1,
+                },
+            ),
+            &mut deposits,
+        )?;
+        assert!(account
+            .apply_timed_transaction(
+                at(
+                    120 * DAY + 1,
+                    Transaction::Dispute {
+                        amount: None,
+                        client: 1,
+                        tx: 2,
+                    },
+                ),
+                &mut deposits
+            )
+            .is_err());
+        assert_eq!(account.held, 10.into());
+        Ok(())
+    }
+
+    #[test]
+    fn authorizations_expire_once_their_window_has_passed() -> Result<()> {
+        let mut account = Account::new(1).with_windows(Windows {
+            authorization: Some(7 * DAY),
+            ..Default::default()
+        });
+        let mut deposits = MemoryStore::default();
+        account.apply_timed_transaction(
+            at(
+                0,
+                Transaction::Deposit {
+                    amount: Some(10.into()),
+                    client: 1,
+                    tx: 1,
+                },
+            ),
+            &mut deposits,
+        )?;
+        account.apply_timed_transaction(
+            at(
+                DAY,
+                Transaction::Authorize {
+                    amount: Some(4.into()),
+                    client: 1,
+                    tx: 2,
+                },
+            ),
+            &mut deposits,
+        )?;
+        assert!(account.expired_authorizations(Some(8 * DAY)).is_empty());
+        assert_eq!(account.expired_authorizations(Some(8 * DAY + 1)), vec![2]);
+        Ok(())
+    }
+
+    #[test]
+    fn a_partially_reversed_deposit_can_only_be_disputed_for_what_is_left() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        account.apply_transaction(
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            &mut deposits,
+        )?;
+        account.apply_transaction(
+            Transaction::Reversal {
+                client: 1,
+                tx: 1,
+                amount: Some(4.into()),
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 6.into());
+        account.apply_transaction(
+            Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.held, 6.into());
+        // What is under dispute can't be reversed.
+        assert!(account
+            .apply_transaction(
+                Transaction::Reversal {
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                },
+                &mut deposits,
+            )
+            .is_err());
+        assert_eq!(account.held, 6.into());
+        Ok(())
+    }
+
+    #[test]
+    fn a_withdrawal_can_be_refunded_up_to_what_is_left_of_it() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        for transaction in [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(8.into()),
+            },
+            Transaction::Reversal {
+                client: 1,
+                tx: 2,
+                amount: Some(3.into()),
+            },
+            Transaction::Refund {
+                client: 1,
+                tx: 2,
+                amount: Some(2.into()),
+            },
+        ] {
+            account.apply_transaction(transaction, &mut deposits)?;
+        }
+        assert_eq!(account.available, 7.into());
+        assert!(account
+            .apply_transaction(
+                Transaction::Refund {
+                    client: 1,
+                    tx: 2,
+                    amount: Some(4.into()),
+                },
+                &mut deposits,
+            )
+            .is_err());
+        // Deposits can be reversed but not refunded.
+        assert!(account
+            .apply_transaction(
+                Transaction::Refund {
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                },
+                &mut deposits,
+            )
+            .is_err());
+        account.apply_transaction(
+            Transaction::Refund {
+                client: 1,
+                tx: 2,
+                amount: None,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, 10.into());
+        Ok(())
+    }
+
+    #[test]
+    fn fees_are_charged_with_the_transaction_or_not_at_all() -> Result<()> {
+        let rule = |kind: &str, flat, percentage| FeeRule {
+            kind: kind.to_string(),
+            tier: None,
+            above: Decimal::ZERO,
+            flat,
+            percentage,
+            min: None,
+            max: None,
+        };
+        let fees = FeeSchedule::new(vec![
+            rule("withdrawal", Decimal::ONE, 10.into()),
+            rule("deposit", Decimal::new(5, 1), Decimal::ZERO),
+        ]);
+        let mut account = Account::new(1).with_fees(Rc::new(fees));
+        let mut deposits = MemoryStore::default();
+        // A deposit that can't pay its fee isn't there to be disputed.
+        assert!(account
+            .apply_transaction(
+                Transaction::Deposit {
+                    client: 1,
+                    tx: 9,
+                    amount: Some(Decimal::new(2, 1)),
+                },
+                &mut deposits,
+            )
+            .is_err());
+        assert!(account
+            .apply_transaction(
+                Transaction::Dispute {
+                    client: 1,
+                    tx: 9,
+                    amount: None,
+                },
+                &mut deposits,
+            )
+            .is_err());
         assert_eq!(account.held, 0.into());
+        account.apply_transaction(
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(20.into()),
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, Decimal::new(195, 1));
+        // 10 + a fee of 2 is fine, 8 + a fee of 1.8 is not.
+        account.apply_transaction(
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(10.into()),
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.available, Decimal::new(75, 1));
+        assert!(account
+            .apply_transaction(
+                Transaction::Withdrawal {
+                    client: 1,
+                    tx: 3,
+                    amount: Some(7.into()),
+                },
+                &mut deposits,
+            )
+            .is_err());
+        assert_eq!(account.available, Decimal::new(75, 1));
+        let fee_revenue: Decimal = account
+            .take_postings()
+            .iter()
+            .filter(|posting| posting.account == LedgerAccount::FeeRevenue)
+            .map(|posting| posting.amount)
+            .sum();
+        assert_eq!(fee_revenue, Decimal::new(25, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn held_funds_are_kept_apart_by_reason_and_legal_holds_release_in_full() -> Result<()> {
+        let mut account = Account::new(1);
+        let mut deposits = MemoryStore::default();
+        for transaction in [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(100.into()),
+            },
+            Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            Transaction::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Some(50.into()),
+            },
+            Transaction::Authorize {
+                client: 1,
+                tx: 3,
+                amount: Some(20.into()),
+            },
+            Transaction::LegalHold {
+                client: 1,
+                tx: 4,
+                amount: Some(10.into()),
+            },
+        ] {
+            account.apply_transaction(transaction, &mut deposits)?;
+        }
+        let holds: Vec<_> = account
+            .holds()
+            .map(|hold| (hold.reason, hold.id, hold.amount))
+            .collect();
+        assert_eq!(
+            holds,
+            vec![
+                (HoldReason::Dispute, 1, 100.into()),
+                (HoldReason::Authorization, 3, 20.into()),
+                (HoldReason::Legal, 4, 10.into()),
+            ]
+        );
+        assert_eq!(account.held, 130.into());
         assert_eq!(account.available, 20.into());
-        assert!(account.locked);
+        // Neither a second hold with the same id nor one for more than is available.
+        for (tx, amount) in [(4, 1), (5, 21)] {
+            assert!(account
+                .apply_transaction(
+                    Transaction::LegalHold {
+                        client: 1,
+                        tx,
+                        amount: Some(amount.into()),
+                    },
+                    &mut deposits,
+                )
+                .is_err());
+        }
+        account.apply_transaction(
+            Transaction::LegalRelease {
+                client: 1,
+                tx: 4,
+                amount: None,
+            },
+            &mut deposits,
+        )?;
+        assert_eq!(account.held, 120.into());
+        assert_eq!(account.available, 30.into());
+        assert_eq!(account.holds().count(), 2);
+        assert!(account
+            .apply_transaction(
+                Transaction::LegalRelease {
+                    client: 1,
+                    tx: 4,
+                    amount: None,
+                },
+                &mut deposits,
+            )
+            .is_err());
         Ok(())
     }
 }
@@ -0,0 +1,298 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::account::serialize_with_fixed_digits;
+use crate::clock::{has_elapsed, Timestamp, DAY};
+use crate::Transaction;
+
+// What compliance has to be told about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    // Any deposit or withdrawal of at least the threshold.
+    LargeTransaction {
+        threshold: Decimal,
+    },
+    // Count or more deposits within the window, each just below the threshold,
+    // i.e within margin percent of it.
+    Structuring {
+        threshold: Decimal,
+        margin: Decimal,
+        count: usize,
+        window: u64,
+    },
+}
+
+impl Rule {
+    fn name(&self) -> &'static str {
+        match self {
+            Rule::LargeTransaction { .. } => "large_transaction",
+            Rule::Structuring { .. } => "structuring",
+        }
+    }
+}
+
+// A transaction seen by the monitor, as it goes into a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Seen {
+    timestamp: Option<Timestamp>,
+    tx: u32,
+    amount: Decimal,
+}
+
+// Everything compliance has to be told about. No rules, no reports.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct AmlRules {
+    rules: Vec<Rule>,
+}
+
+impl AmlRules {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        AmlRules { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+// Watches the transaction stream for whatever the rules say compliance has to
+// be told about, keeping the recent deposits below each structuring threshold
+// of every client.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    rules: Rc<AmlRules>,
+    // Recent deposits by client and rule.
+    deposits: HashMap<(u16, usize), VecDeque<Seen>>,
+}
+
+impl Monitor {
+    pub fn new(rules: Rc<AmlRules>) -> Self {
+        Monitor {
+            rules,
+            deposits: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Looks at an applied transaction at `now`, returning what to report. Once a
+    // pattern of deposits has been reported they no longer count towards another.
+    pub fn observe(&mut self, transaction: &Transaction, now: Option<Timestamp>) -> Vec<Report> {
+        let (client, tx) = (*transaction.get_client(), *transaction.get_tx());
+        let amount = match transaction {
+            Transaction::Deposit {
+                amount: Some(amount),
+                ..
+            }
+            | Transaction::Withdrawal {
+                amount: Some(amount),
+                ..
+            } => *amount,
+            _ => return Vec::new(),
+        };
+        let is_deposit = matches!(transaction, Transaction::Deposit { .. });
+        let mut reports = Vec::new();
+        for (i, rule) in self.rules.rules.iter().enumerate() {
+            match *rule {
+                Rule::LargeTransaction { threshold } if amount >= threshold => {
+                    reports.push(Report::new(
+                        rule.name(),
+                        client,
+                        &[Seen {
+                            timestamp: now,
+                            tx,
+                            amount,
+                        }],
+                    ));
+                }
+                Rule::Structuring {
+                    threshold,
+                    margin,
+                    count,
+                    window,
+                } if is_deposit
+                    && amount < threshold
+                    && amount * Decimal::ONE_HUNDRED
+                        >= threshold * (Decimal::ONE_HUNDRED - margin) =>
+                {
+                    let deposits = self.deposits.entry((client, i)).or_default();
+                    while let Some(deposit) = deposits.front() {
+                        if !has_elapsed(deposit.timestamp, now, Some(window)) {
+                            break;
+                        }
+                        deposits.pop_front();
+                    }
+                    deposits.push_back(Seen {
+                        timestamp: now,
+                        tx,
+                        amount,
+                    });
+                    if deposits.len() >= count {
+                        let deposits: Vec<_> = deposits.drain(..).collect();
+                        reports.push(Report::new(rule.name(), client, &deposits));
+                    }
+                }
+                _ => (),
+            }
+        }
+        reports
+    }
+}
+
+// A line of the report for compliance: the transactions that made it and the
+// window they fall within.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub report: &'static str,
+    pub client: u16,
+    // The tx ids and amounts of the transactions, separated by semicolons.
+    pub txs: String,
+    pub amounts: String,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub total: Decimal,
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+}
+
+impl Report {
+    fn new(report: &'static str, client: u16, seen: &[Seen]) -> Self {
+        let join = |values: Vec<String>| values.join(";");
+        Report {
+            report,
+            client,
+            txs: join(seen.iter().map(|seen| seen.tx.to_string()).collect()),
+            amounts: join(
+                seen.iter()
+                    .map(|seen| format!("{:.4}", seen.amount))
+                    .collect(),
+            ),
+            total: seen.iter().map(|seen| seen.amount).sum(),
+            from: seen.iter().filter_map(|seen| seen.timestamp).min(),
+            to: seen.iter().filter_map(|seen| seen.timestamp).max(),
+        }
+    }
+}
+
+// The AML rules file has a row per rule, e.g:
+//
+// rule,              threshold, margin, count, days
+// large_transaction,     10000,       ,      ,
+// structuring,           10000,     10,     3,    1
+//
+// reporting deposits and withdrawals of 10000 or more, as well as 3 or more
+// deposits within a day each between 9000 and 10000.
+#[derive(Deserialize, Debug)]
+struct RuleEntry {
+    rule: String,
+    threshold: Decimal,
+    margin: Option<Decimal>,
+    count: Option<usize>,
+    days: Option<u64>,
+}
+
+impl TryFrom<RuleEntry> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: RuleEntry) -> Result<Self> {
+        if entry.threshold <= Decimal::ZERO {
+            return Err(anyhow!("{} needs a positive threshold", entry.rule));
+        }
+        match entry.rule.as_str() {
+            "large_transaction" => Ok(Rule::LargeTransaction {
+                threshold: entry.threshold,
+            }),
+            "structuring" => {
+                let (margin, count, days) = match (entry.margin, entry.count, entry.days) {
+                    (Some(margin), Some(count), Some(days)) => (margin, count, days),
+                    _ => return Err(anyhow!("structuring needs a margin, a count and days")),
+                };
+                if margin <= Decimal::ZERO || margin > Decimal::ONE_HUNDRED || count == 0 {
+                    return Err(anyhow!(
+                        "structuring needs a margin between 0 and 100 and a count of at least one"
+                    ));
+                }
+                Ok(Rule::Structuring {
+                    threshold: entry.threshold,
+                    margin,
+                    count,
+                    window: days * DAY,
+                })
+            }
+            rule => Err(anyhow!("unknown aml rule {}", rule)),
+        }
+    }
+}
+
+pub fn load_aml_rules<P: AsRef<Path>>(path: P) -> Result<AmlRules> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut rules = Vec::new();
+    for result in reader.deserialize::<RuleEntry>() {
+        rules.push(Rule::try_from(result?)?);
+    }
+    Ok(AmlRules::new(rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AmlRules, Monitor, Rule};
+    use crate::clock::DAY;
+    use crate::Transaction;
+    use rust_decimal::Decimal;
+    use std::rc::Rc;
+
+    #[test]
+    fn large_transactions_and_deposits_just_below_the_threshold_are_reported() {
+        let mut monitor = Monitor::new(Rc::new(AmlRules::new(vec![
+            Rule::LargeTransaction {
+                threshold: 10000.into(),
+            },
+            Rule::Structuring {
+                threshold: 10000.into(),
+                margin: 10.into(),
+                count: 3,
+                window: DAY,
+            },
+        ])));
+        let deposit = |tx, amount: i64| Transaction::Deposit {
+            client: 1,
+            tx,
+            amount: Some(amount.into()),
+        };
+        let reports = monitor.observe(
+            &Transaction::Withdrawal {
+                client: 1,
+                tx: 1,
+                amount: Some(10000.into()),
+            },
+            Some(0),
+        );
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].report, "large_transaction");
+        // Too small to count, then one that is forgotten by the time of the next.
+        assert!(monitor.observe(&deposit(2, 8999), Some(0)).is_empty());
+        assert!(monitor.observe(&deposit(3, 9000), Some(0)).is_empty());
+        assert!(monitor.observe(&deposit(4, 9500), Some(2 * DAY)).is_empty());
+        assert!(monitor.observe(&deposit(5, 9900), Some(2 * DAY)).is_empty());
+        let reports = monitor.observe(&deposit(6, 9999), Some(3 * DAY));
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].report, "structuring");
+        assert_eq!(reports[0].txs, "4;5;6");
+        assert_eq!(reports[0].total, Decimal::from(29399));
+        assert_eq!(
+            (reports[0].from, reports[0].to),
+            (Some(2 * DAY), Some(3 * DAY))
+        );
+        // Those deposits have been reported and don't count again.
+        assert!(monitor.observe(&deposit(7, 9999), Some(3 * DAY)).is_empty());
+    }
+}
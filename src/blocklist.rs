@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// What happens to the transactions of a blocked client: either they are
+// rejected or they are held for review, set aside until a review_release with
+// their tx id lets them through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Reject,
+    Hold,
+}
+
+// A blocked client along with what we know about them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub client: u16,
+    pub name: Option<String>,
+    pub reason: Option<String>,
+    pub action: Action,
+}
+
+// The error a transaction is rejected with when its client is blocked, so that
+// it can be told apart from other rejections by downcasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blocked(pub Listing);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "client {} is blocked", self.0.client)?;
+        if let Some(name) = &self.0.name {
+            write!(f, " as {}", name)?;
+        }
+        if let Some(reason) = &self.0.reason {
+            write!(f, ": {}", reason)?;
+        }
+        if self.0.action == Action::Hold {
+            write!(f, ", held for review")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Blocked {}
+
+// The blocked clients from a local list file, which is read again whenever it
+// has been modified since it was last read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blocklist {
+    // The file the list was read from, if any.
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    listings: HashMap<u16, Listing>,
+}
+
+impl Blocklist {
+    // A list that wasn't read from a file, and so is never read again.
+    pub fn new(listings: Vec<Listing>) -> Self {
+        Blocklist {
+            path: None,
+            modified: None,
+            listings: listings
+                .into_iter()
+                .map(|listing| (listing.client, listing))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, client: u16) -> Option<&Listing> {
+        self.listings.get(&client)
+    }
+
+    // Reads the list again if the file has been modified since. Returns whether it
+    // was. Should it fail, the list is left as it was.
+    pub fn reload_if_modified(&mut self) -> Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        if modified(path)? == self.modified {
+            return Ok(false);
+        }
+        *self = load_blocklist(path)?;
+        Ok(true)
+    }
+}
+
+fn modified(path: &Path) -> Result<Option<SystemTime>> {
+    Ok(fs::metadata(path)?.modified().ok())
+}
+
+// The blocklist file has a row per blocked client, with an optional name and
+// reason and whether to reject (the default) or hold their transactions, e.g:
+//
+// client, name,         reason,            action
+// 13,     Acme Ltd,     sanctions list,
+// 42,     ,             internal review,   hold
+#[derive(Deserialize, Debug)]
+struct ListingEntry {
+    client: u16,
+    name: Option<String>,
+    reason: Option<String>,
+    action: Option<String>,
+}
+
+// Empty columns are no name, no reason and rejecting.
+impl TryFrom<ListingEntry> for Listing {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: ListingEntry) -> Result<Self> {
+        let action = match entry.action.as_deref() {
+            None | Some("") | Some("reject") => Action::Reject,
+            Some("hold") => Action::Hold,
+            Some(action) => {
+                return Err(anyhow!(
+                    "client {}: blocked clients are either rejected or held, not {}",
+                    entry.client,
+                    action
+                ))
+            }
+        };
+        Ok(Listing {
+            client: entry.client,
+            name: entry.name.filter(|name| !name.is_empty()),
+            reason: entry.reason.filter(|reason| !reason.is_empty()),
+            action,
+        })
+    }
+}
+
+pub fn load_blocklist<P: AsRef<Path>>(path: P) -> Result<Blocklist> {
+    let path = path.as_ref();
+    let modified = modified(path)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut clients = HashSet::new();
+    let mut listings = Vec::new();
+    for result in reader.deserialize::<ListingEntry>() {
+        let listing = Listing::try_from(result?)?;
+        if !clients.insert(listing.client) {
+            return Err(anyhow!(
+                "client {} is blocked more than once",
+                listing.client
+            ));
+        }
+        listings.push(listing);
+    }
+    Ok(Blocklist {
+        path: Some(path.to_path_buf()),
+        modified,
+        ..Blocklist::new(listings)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_blocklist, Action, Blocked};
+    use anyhow::Result;
+    use std::{env, fs, time::SystemTime};
+
+    #[test]
+    fn the_list_is_read_again_once_modified() -> Result<()> {
+        let path = env::temp_dir().join(format!("paperstack-blocklist-{}", std::process::id()));
+        fs::write(&path, "client,name,reason,action\n13,Acme Ltd,sanctions,\n")?;
+        let mut blocklist = load_blocklist(&path)?;
+        let listing = blocklist.get(13).expect("client 13 to be blocked").clone();
+        assert_eq!(listing.action, Action::Reject);
+        assert_eq!(
+            Blocked(listing).to_string(),
+            "client 13 is blocked as Acme Ltd: sanctions"
+        );
+        assert!(!blocklist.reload_if_modified()?);
+        fs::write(&path, "client,name,reason,action\n42,,,hold\n")?;
+        // Make sure the modification time differs, however coarse it is.
+        fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))?;
+        assert!(blocklist.reload_if_modified()?);
+        assert!(blocklist.get(13).is_none());
+        assert_eq!(
+            blocklist.get(42).map(|listing| listing.action),
+            Some(Action::Hold)
+        );
+        // A list that can't be read, half-written or gone, leaves the previous one be.
+        fs::write(
+            &path,
+            "client,name,reason,action
+42,,,maybe
+",
+        )?;
+        assert!(blocklist.reload_if_modified().is_err());
+        fs::remove_file(&path)?;
+        assert!(blocklist.reload_if_modified().is_err());
+        assert!(blocklist.get(42).is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::Path};
+
+use crate::account::serialize_with_fixed_digits;
+use crate::clock::{Timestamp, DAY};
+
+// Where a dispute case is at. An inquiry may come before the dispute, and a
+// chargeback may be contested by representment, which goes to pre-arbitration
+// and then arbitration unless it is resolved first.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Inquiry,
+    Dispute,
+    Chargeback,
+    Representment,
+    PreArbitration,
+    Arbitration,
+    Resolved,
+}
+
+impl Stage {
+    // Whether a case may move on to this stage from the given one, None being
+    // no case at all.
+    fn follows(self, from: Option<Stage>) -> bool {
+        matches!(
+            (from, self),
+            (None, Stage::Inquiry)
+                | (None | Some(Stage::Inquiry), Stage::Dispute)
+                | (
+                    Some(Stage::Inquiry | Stage::Dispute | Stage::Representment),
+                    Stage::Resolved
+                )
+                | (Some(Stage::Dispute), Stage::Chargeback)
+                | (Some(Stage::Chargeback), Stage::Representment)
+                | (Some(Stage::Representment), Stage::PreArbitration)
+                | (Some(Stage::PreArbitration), Stage::Arbitration)
+        )
+    }
+
+    // Closed cases go no further and have no deadline.
+    pub fn is_closed(self) -> bool {
+        matches!(self, Stage::Resolved | Stage::Arbitration)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Stage::Inquiry => "inquiry",
+            Stage::Dispute => "dispute",
+            Stage::Chargeback => "chargeback",
+            Stage::Representment => "representment",
+            Stage::PreArbitration => "pre_arbitration",
+            Stage::Arbitration => "arbitration",
+            Stage::Resolved => "resolved",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Fails unless the case id may move on to the given stage from where it is,
+// None being no case at all.
+pub fn check_move(id: u32, from: Option<Stage>, to: Stage) -> Result<()> {
+    if to.follows(from) {
+        return Ok(());
+    }
+    Err(match from {
+        Some(from) => anyhow!("case {} cannot move from {} to {}", id, from, to),
+        None => anyhow!("there is no case {} to move to {}", id, to),
+    })
+}
+
+// A stage a case has been in, since when.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageChange {
+    pub stage: Stage,
+    pub timestamp: Option<Timestamp>,
+}
+
+// Free text added to a case, e.g by whoever is working it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub timestamp: Option<Timestamp>,
+    pub text: String,
+}
+
+// A dispute case, identified by the deposit it is about, with every stage it
+// has been through, the current one last, and the notes added along the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub id: u32,
+    pub client: u16,
+    pub amount: Decimal,
+    stages: Vec<StageChange>,
+    notes: Vec<Note>,
+}
+
+impl Case {
+    // Opens a case at `now`, unless it can't start out at the given stage.
+    pub fn open(
+        id: u32,
+        client: u16,
+        amount: Decimal,
+        stage: Stage,
+        now: Option<Timestamp>,
+    ) -> Result<Self> {
+        check_move(id, None, stage)?;
+        Ok(Case {
+            id,
+            client,
+            amount,
+            stages: vec![StageChange {
+                stage,
+                timestamp: now,
+            }],
+            notes: Vec::new(),
+        })
+    }
+
+    // Moves the case on to the given stage at `now`, unless it can't go there
+    // from where it is.
+    pub fn advance(&mut self, stage: Stage, now: Option<Timestamp>) -> Result<()> {
+        check_move(self.id, Some(self.stage()), stage)?;
+        self.stages.push(StageChange {
+            stage,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    pub fn note(&mut self, now: Option<Timestamp>, text: String) {
+        self.notes.push(Note {
+            timestamp: now,
+            text,
+        });
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.current().stage
+    }
+
+    fn current(&self) -> &StageChange {
+        self.stages.last().expect("a case to have a stage")
+    }
+
+    // When the current stage has to be acted upon, if it has a deadline at all.
+    pub fn deadline(&self, deadlines: &Deadlines) -> Option<Timestamp> {
+        let change = self.current();
+        if change.stage.is_closed() {
+            return None;
+        }
+        Some(change.timestamp? + deadlines.0.get(&change.stage)?)
+    }
+
+    // The case as of `now` as a line in the cases report.
+    pub fn line(&self, deadlines: &Deadlines, now: Option<Timestamp>) -> CaseLine {
+        let deadline = self.deadline(deadlines);
+        CaseLine {
+            client: self.client,
+            case: self.id,
+            stage: self.stage(),
+            amount: self.amount,
+            opened: self.stages[0].timestamp,
+            since: self.current().timestamp,
+            deadline,
+            overdue: deadline.is_some_and(|deadline| now.is_some_and(|now| now > deadline)),
+            notes: self.notes.len(),
+        }
+    }
+}
+
+// The full story of a case, stage by stage with the notes in between.
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "case {} of client {} for {:.4}",
+            self.id, self.client, self.amount
+        )?;
+        writeln!(f)?;
+        let at = |timestamp: Option<Timestamp>| {
+            timestamp.map_or("at an unknown time".to_string(), |timestamp| {
+                format!("at {}", timestamp)
+            })
+        };
+        let mut notes = self.notes.iter().peekable();
+        for change in &self.stages {
+            while let Some(note) = notes.next_if(|note| note.timestamp < change.timestamp) {
+                writeln!(f, "  note {}: {}", at(note.timestamp), note.text)?;
+            }
+            writeln!(f, "{} {}", change.stage, at(change.timestamp))?;
+        }
+        for note in notes {
+            writeln!(f, "  note {}: {}", at(note.timestamp), note.text)?;
+        }
+        Ok(())
+    }
+}
+
+// A case as a line in the cases report.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CaseLine {
+    pub client: u16,
+    pub case: u32,
+    pub stage: Stage,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub amount: Decimal,
+    pub opened: Option<Timestamp>,
+    // When the case entered its current stage.
+    pub since: Option<Timestamp>,
+    pub deadline: Option<Timestamp>,
+    pub overdue: bool,
+    // How many notes there are, see the case command for what they say.
+    pub notes: usize,
+}
+
+// How long, in seconds, a case may stay in each stage before it is overdue.
+// Stages without a deadline never are.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Deadlines(pub HashMap<Stage, u64>);
+
+// The case deadlines file has a row per stage with the days it may last, e.g:
+//
+// stage,           days
+// inquiry,           10
+// chargeback,        30
+// pre_arbitration,   10
+#[derive(Deserialize, Debug)]
+struct DeadlineEntry {
+    stage: Stage,
+    days: u64,
+}
+
+pub fn load_case_deadlines<P: AsRef<Path>>(path: P) -> Result<Deadlines> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut deadlines = HashMap::new();
+    for result in reader.deserialize::<DeadlineEntry>() {
+        let entry = result?;
+        if entry.stage.is_closed() {
+            return Err(anyhow!("{} closes a case and has no deadline", entry.stage));
+        }
+        if deadlines.insert(entry.stage, entry.days * DAY).is_some() {
+            return Err(anyhow!("{} has more than one deadline", entry.stage));
+        }
+    }
+    Ok(Deadlines(deadlines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_case_deadlines, Case, Deadlines, Stage};
+    use crate::clock::DAY;
+    use anyhow::Result;
+    use std::{collections::HashMap, env, fs};
+
+    #[test]
+    fn cases_move_through_the_stages_in_order_and_are_overdue_past_their_deadline() {
+        let deadlines = Deadlines(HashMap::from([(Stage::Chargeback, 30 * DAY)]));
+        assert!(Case::open(1, 2, 100.into(), Stage::Chargeback, Some(0)).is_err());
+        let mut case = Case::open(1, 2, 100.into(), Stage::Inquiry, Some(0)).unwrap();
+        case.advance(Stage::Dispute, Some(DAY)).unwrap();
+        assert_eq!(case.deadline(&deadlines), None);
+        case.advance(Stage::Chargeback, Some(2 * DAY)).unwrap();
+        assert_eq!(case.deadline(&deadlines), Some(32 * DAY));
+        assert!(!case.line(&deadlines, Some(32 * DAY)).overdue);
+        assert!(case.line(&deadlines, Some(32 * DAY + 1)).overdue);
+        let error = case.advance(Stage::Resolved, Some(3 * DAY)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "case 1 cannot move from chargeback to resolved"
+        );
+        case.advance(Stage::Representment, Some(3 * DAY)).unwrap();
+        case.advance(Stage::PreArbitration, Some(4 * DAY)).unwrap();
+        case.advance(Stage::Arbitration, Some(5 * DAY)).unwrap();
+        assert_eq!(case.stage(), Stage::Arbitration);
+        assert_eq!(case.stages.len(), 6);
+        assert!(case.line(&deadlines, Some(100 * DAY)).deadline.is_none());
+        assert!(case.advance(Stage::Resolved, Some(6 * DAY)).is_err());
+    }
+
+    #[test]
+    fn deadlines_are_loaded_in_days_for_stages_that_can_be_overdue() -> Result<()> {
+        let path =
+            env::temp_dir().join(format!("paperstack-case-deadlines-{}", std::process::id()));
+        fs::write(&path, "stage,days\ninquiry,10\nchargeback,30\n")?;
+        let deadlines = load_case_deadlines(&path);
+        fs::write(&path, "stage,days\nresolved,10\n")?;
+        let closed = load_case_deadlines(&path);
+        fs::remove_file(&path)?;
+        assert_eq!(
+            deadlines?,
+            Deadlines(HashMap::from([
+                (Stage::Inquiry, 10 * DAY),
+                (Stage::Chargeback, 30 * DAY)
+            ]))
+        );
+        assert!(closed.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,737 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::{ffi::OsString, path::PathBuf};
+
+use crate::account::NegativeBalancePolicy;
+use crate::clock::{Timestamp, DAY};
+use crate::engine::{Config, Cutoff};
+use crate::ratio::RatioPolicy;
+use crate::statement::Period;
+
+// What to do with the transactions once they have been processed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    // Write every account to stdout.
+    #[default]
+    Accounts,
+    // Write the history of a single client to stdout.
+    History {
+        client: u16,
+    },
+    // Explain how a single client's balances came to be.
+    Explain {
+        client: u16,
+    },
+    // Tell the story of a single dispute case.
+    Case {
+        client: u16,
+        tx: u32,
+    },
+    // Write every account to stdout as it was at some point in the input.
+    Balances {
+        as_of: Cutoff,
+    },
+    // Write statements for a period, for one client or all of them.
+    Statement {
+        client: Option<u16>,
+        period: Period,
+        format: Format,
+    },
+    // Compare every account against balances from elsewhere, failing on any
+    // difference above the tolerance.
+    Reconcile {
+        against: PathBuf,
+        tolerance: Decimal,
+    },
+    // Compare two files of accounts rather than processing transactions.
+    Diff {
+        before: PathBuf,
+        after: PathBuf,
+    },
+}
+
+// How to write documents meant for people as well as machines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Csv,
+    Html,
+}
+
+// Cutoffs are given as a timestamp or as row:N.
+fn cutoff_of(option: &str, args: &mut impl Iterator<Item = OsString>) -> Result<Cutoff> {
+    let value = value_of(option, args)?;
+    let cutoff = value
+        .to_str()
+        .and_then(|cutoff| match cutoff.strip_prefix("row:") {
+            Some(row) => row.parse().ok().map(Cutoff::Row),
+            None => cutoff.parse().ok().map(Cutoff::Timestamp),
+        });
+    cutoff.ok_or_else(|| {
+        anyhow!(
+            "option {} expects a timestamp or row:N, got {:?}",
+            option,
+            value
+        )
+    })
+}
+
+// Everything we can be told on the command line. We parse this by hand rather
+// than pulling in an argument parsing crate to keep the dependencies down.
+//
+// paperstack [command] [options] <transactions.csv>
+// paperstack diff <before.csv> <after.csv>
+#[derive(Debug, Default)]
+pub struct Options {
+    pub command: Command,
+    pub csv_path: OsString,
+    pub config: Config,
+    // A csv of client, limit, mode and fee for clients with an approved credit line.
+    pub overdraft_limits: Option<PathBuf>,
+    // Transactions to try out on top of the others, without committing them.
+    pub dry_run: Option<PathBuf>,
+    // A csv of fee rules, see the fee module.
+    pub fees: Option<PathBuf>,
+    // A csv of client and tier, for clients whose fees depend on their tier.
+    pub client_tiers: Option<PathBuf>,
+    // A csv of interest rates, see the interest module.
+    pub interest_rates: Option<PathBuf>,
+    // How often accrued interest is credited, in days.
+    pub interest_posting_days: Option<u64>,
+    // Where to write a row for every balance change, if anywhere.
+    pub timeseries: Option<PathBuf>,
+    // Where to write every ledger posting, if anywhere.
+    pub journal: Option<PathBuf>,
+    // A csv of withdrawal limits, see the limit module.
+    pub withdrawal_limits: Option<PathBuf>,
+    // A csv of risk rules, see the risk module.
+    pub risk_rules: Option<PathBuf>,
+    // The scores at which risky transactions are flagged, held and rejected.
+    pub risk_thresholds: Option<[u32; 3]>,
+    // Where to write alerts for risky transactions, if anywhere but stderr.
+    pub alerts: Option<PathBuf>,
+    // A csv of blocked clients, see the blocklist module.
+    pub blocklist: Option<PathBuf>,
+    // A csv of AML rules, see the aml module.
+    pub aml_rules: Option<PathBuf>,
+    // Where to write what the AML rules found.
+    pub aml_report: Option<PathBuf>,
+    // Where to write the clients over a chargeback ratio threshold, if anywhere.
+    pub chargeback_report: Option<PathBuf>,
+    // A csv of stage and days for dispute case stages with a deadline.
+    pub case_deadlines: Option<PathBuf>,
+    // Where to write every dispute case, if anywhere.
+    pub cases: Option<PathBuf>,
+    // A csv of client, percentage and days for clients with a rolling reserve.
+    pub reserves: Option<PathBuf>,
+    // Where to write the ledger's trial balance, if anywhere.
+    pub trial_balance: Option<PathBuf>,
+    // Where to write every hold making up the held funds, if anywhere.
+    pub holds: Option<PathBuf>,
+    // Where to spill deposits that don't fit in memory, if anywhere.
+    pub deposit_store: Option<PathBuf>,
+    // How many deposits to keep in memory when spilling to disk.
+    pub deposit_cache: usize,
+}
+
+// Enough to keep a good while of recent deposits in memory without using much of it.
+const DEFAULT_DEPOSIT_CACHE: usize = 1_000_000;
+
+const COMMANDS: [&str; 7] = [
+    "history",
+    "explain",
+    "case",
+    "balances",
+    "statement",
+    "reconcile",
+    "diff",
+];
+
+impl Options {
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self> {
+        let mut options = Options {
+            deposit_cache: DEFAULT_DEPOSIT_CACHE,
+            ..Default::default()
+        };
+        let mut paths = Vec::new();
+        let mut command = None;
+        let mut client = None;
+        let mut tx = None;
+        let mut as_of = None;
+        let mut period = Period::default();
+        let mut format = Format::default();
+        let mut against = None;
+        let mut tolerance = Decimal::ZERO;
+        let mut ratio_thresholds = None;
+        let mut ratio_window = None;
+        let mut lock_on_critical_ratio = false;
+        let mut args = args.into_iter().peekable();
+        // The command, if any, comes first.
+        if let Some(name) = args.peek().and_then(|arg| arg.to_str()) {
+            if COMMANDS.contains(&name) {
+                command = Some(name.to_string());
+                args.next();
+            }
+        }
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--as-of") => as_of = Some(cutoff_of("--as-of", &mut args)?),
+                Some("--from") => period.from = Some(timestamp_of("--from", &mut args)?),
+                Some("--to") => period.to = Some(timestamp_of("--to", &mut args)?),
+                Some("--format") => {
+                    let value = value_of("--format", &mut args)?;
+                    format = match value.to_str() {
+                        Some("text") => Format::Text,
+                        Some("csv") => Format::Csv,
+                        Some("html") => Format::Html,
+                        _ => {
+                            return Err(anyhow!(
+                                "option --format expects text, csv or html, got {:?}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                Some("--against") => against = Some(value_of("--against", &mut args)?.into()),
+                Some("--tolerance") => {
+                    let value = value_of("--tolerance", &mut args)?;
+                    tolerance = value
+                        .to_str()
+                        .and_then(|tolerance| tolerance.parse().ok())
+                        .ok_or_else(|| {
+                            anyhow!("option --tolerance expects an amount, got {:?}", value)
+                        })?
+                }
+                Some("--client") => {
+                    let value = value_of("--client", &mut args)?;
+                    client = Some(
+                        value
+                            .to_str()
+                            .and_then(|c| c.parse::<u16>().ok())
+                            .ok_or_else(|| {
+                                anyhow!("option --client expects a client id, got {:?}", value)
+                            })?,
+                    )
+                }
+                Some("--tx") => {
+                    let value = value_of("--tx", &mut args)?;
+                    tx = Some(
+                        value
+                            .to_str()
+                            .and_then(|tx| tx.parse::<u32>().ok())
+                            .ok_or_else(|| {
+                                anyhow!("option --tx expects a transaction id, got {:?}", value)
+                            })?,
+                    )
+                }
+                Some("--case-deadlines") => {
+                    options.case_deadlines = Some(value_of("--case-deadlines", &mut args)?.into())
+                }
+                Some("--cases") => options.cases = Some(value_of("--cases", &mut args)?.into()),
+                Some("--blocklist") => {
+                    options.blocklist = Some(value_of("--blocklist", &mut args)?.into())
+                }
+                Some("--aml-rules") => {
+                    options.aml_rules = Some(value_of("--aml-rules", &mut args)?.into())
+                }
+                Some("--aml-report") => {
+                    options.aml_report = Some(value_of("--aml-report", &mut args)?.into())
+                }
+                Some("--chargeback-ratio") => {
+                    ratio_thresholds = Some(thresholds_of("--chargeback-ratio", &mut args)?)
+                }
+                Some("--chargeback-ratio-days") => {
+                    ratio_window = Some(days_of("--chargeback-ratio-days", &mut args)?)
+                }
+                Some("--lock-on-critical-ratio") => lock_on_critical_ratio = true,
+                Some("--chargeback-report") => {
+                    options.chargeback_report =
+                        Some(value_of("--chargeback-report", &mut args)?.into())
+                }
+                Some("--allow-negative-chargebacks") => {
+                    options.config.negative_balance_policy =
+                        NegativeBalancePolicy::AllowOnChargeback
+                }
+                Some("--dispute-window-days") => {
+                    options.config.windows.dispute =
+                        Some(days_of("--dispute-window-days", &mut args)?)
+                }
+                Some("--deposit-retention-days") => {
+                    options.config.windows.retention =
+                        Some(days_of("--deposit-retention-days", &mut args)?)
+                }
+                Some("--authorization-expiry-days") => {
+                    options.config.windows.authorization =
+                        Some(days_of("--authorization-expiry-days", &mut args)?)
+                }
+                Some("--dry-run") => {
+                    options.dry_run = Some(value_of("--dry-run", &mut args)?.into())
+                }
+                Some("--fees") => options.fees = Some(value_of("--fees", &mut args)?.into()),
+                Some("--client-tiers") => {
+                    options.client_tiers = Some(value_of("--client-tiers", &mut args)?.into())
+                }
+                Some("--interest-rates") => {
+                    options.interest_rates = Some(value_of("--interest-rates", &mut args)?.into())
+                }
+                Some("--interest-posting-days") => {
+                    options.interest_posting_days =
+                        Some(days_of("--interest-posting-days", &mut args)? / DAY)
+                }
+                Some("--withdrawal-limits") => {
+                    options.withdrawal_limits =
+                        Some(value_of("--withdrawal-limits", &mut args)?.into())
+                }
+                Some("--risk-rules") => {
+                    options.risk_rules = Some(value_of("--risk-rules", &mut args)?.into())
+                }
+                Some("--risk-thresholds") => {
+                    options.risk_thresholds = Some(scores_of("--risk-thresholds", &mut args)?)
+                }
+                Some("--alerts") => options.alerts = Some(value_of("--alerts", &mut args)?.into()),
+                Some("--reserves") => {
+                    options.reserves = Some(value_of("--reserves", &mut args)?.into())
+                }
+                Some("--timeseries") => {
+                    options.timeseries = Some(value_of("--timeseries", &mut args)?.into())
+                }
+                Some("--journal") => {
+                    options.journal = Some(value_of("--journal", &mut args)?.into())
+                }
+                Some("--trial-balance") => {
+                    options.trial_balance = Some(value_of("--trial-balance", &mut args)?.into())
+                }
+                Some("--holds") => options.holds = Some(value_of("--holds", &mut args)?.into()),
+                Some("--deposit-store") => {
+                    options.deposit_store = Some(value_of("--deposit-store", &mut args)?.into())
+                }
+                Some("--deposit-cache") => {
+                    let value = value_of("--deposit-cache", &mut args)?;
+                    options.deposit_cache = value
+                        .to_str()
+                        .and_then(|size| size.parse().ok())
+                        .ok_or_else(|| {
+                            anyhow!("option --deposit-cache expects a number, got {:?}", value)
+                        })?
+                }
+                Some("--overdraft-limits") => {
+                    options.overdraft_limits =
+                        Some(value_of("--overdraft-limits", &mut args)?.into())
+                }
+                Some(flag) if flag.starts_with("--") => {
+                    return Err(anyhow!("unknown option {}", flag))
+                }
+                _ => paths.push(arg),
+            }
+        }
+        let expected = if command.as_deref() == Some("diff") {
+            2
+        } else {
+            1
+        };
+        if let Some(arg) = paths.get(expected) {
+            return Err(anyhow!("unexpected argument {:?}", arg));
+        }
+        if paths.len() < expected {
+            return Err(anyhow!(
+                "expected {} arguments, but got {}",
+                expected,
+                paths.len()
+            ));
+        }
+        let mut paths = paths.into_iter();
+        options.csv_path = paths.next().unwrap_or_default();
+        options.command = match command.as_deref() {
+            Some("history") => Command::History {
+                client: client.ok_or_else(|| anyhow!("history expects --client"))?,
+            },
+            Some("explain") => Command::Explain {
+                client: client.ok_or_else(|| anyhow!("explain expects --client"))?,
+            },
+            Some("case") => Command::Case {
+                client: client.ok_or_else(|| anyhow!("case expects --client"))?,
+                tx: tx.ok_or_else(|| anyhow!("case expects --tx"))?,
+            },
+            Some("balances") => Command::Balances {
+                as_of: as_of.ok_or_else(|| anyhow!("balances expects --as-of"))?,
+            },
+            Some("statement") => Command::Statement {
+                client,
+                period,
+                format,
+            },
+            Some("reconcile") => Command::Reconcile {
+                against: against.ok_or_else(|| anyhow!("reconcile expects --against"))?,
+                tolerance,
+            },
+            Some("diff") => Command::Diff {
+                before: options.csv_path.clone().into(),
+                after: paths.next().unwrap_or_default().into(),
+            },
+            _ => Command::Accounts,
+        };
+        if options.dry_run.is_some() && options.command != Command::Accounts {
+            return Err(anyhow!("--dry-run can't be combined with a command"));
+        }
+        if options.aml_rules.is_some() != options.aml_report.is_some() {
+            return Err(anyhow!("--aml-rules and --aml-report go together"));
+        }
+        // Tiers only decide which fees and interest rates apply.
+        if options.client_tiers.is_some()
+            && options.fees.is_none()
+            && options.interest_rates.is_none()
+        {
+            return Err(anyhow!("--client-tiers needs --fees or --interest-rates"));
+        }
+        options.config.chargeback_ratio = match ratio_thresholds {
+            Some([warning, critical]) => {
+                let mut policy =
+                    RatioPolicy::new(warning, critical).with_lock(lock_on_critical_ratio);
+                if let Some(window) = ratio_window {
+                    policy = policy.with_window(window);
+                }
+                Some(policy)
+            }
+            None if ratio_window.is_some()
+                || lock_on_critical_ratio
+                || options.chargeback_report.is_some() =>
+            {
+                return Err(anyhow!("chargeback ratio options need --chargeback-ratio"))
+            }
+            None => None,
+        };
+        Ok(options)
+    }
+}
+
+// Fetches the value following an option, e.g the path in `--overdraft-limits limits.csv`.
+fn value_of(option: &str, args: &mut impl Iterator<Item = OsString>) -> Result<OsString> {
+    args.next()
+        .ok_or_else(|| anyhow!("option {} expects a value", option))
+}
+
+// Fetches a timestamp following an option.
+fn timestamp_of(option: &str, args: &mut impl Iterator<Item = OsString>) -> Result<Timestamp> {
+    let value = value_of(option, args)?;
+    value
+        .to_str()
+        .and_then(|timestamp| timestamp.parse().ok())
+        .ok_or_else(|| anyhow!("option {} expects a timestamp, got {:?}", option, value))
+}
+
+// Fetches three increasing scores following an option, separated by commas.
+fn scores_of(option: &str, args: &mut impl Iterator<Item = OsString>) -> Result<[u32; 3]> {
+    let value = value_of(option, args)?;
+    value
+        .to_str()
+        .and_then(|scores| {
+            let scores: Vec<u32> = scores
+                .split(',')
+                .map(|score| score.trim().parse().ok())
+                .collect::<Option<_>>()?;
+            scores.try_into().ok()
+        })
+        .filter(|[flag, hold, reject]: &[u32; 3]| flag <= hold && hold <= reject)
+        .ok_or_else(|| {
+            anyhow!(
+                "option {} expects three increasing scores, e.g 50,75,100, got {:?}",
+                option,
+                value
+            )
+        })
+}
+
+// Fetches a warning and a critical percentage following an option, separated by a comma.
+fn thresholds_of(option: &str, args: &mut impl Iterator<Item = OsString>) -> Result<[Decimal; 2]> {
+    let value = value_of(option, args)?;
+    value
+        .to_str()
+        .and_then(|thresholds| {
+            let thresholds: Vec<Decimal> = thresholds
+                .split(',')
+                .map(|threshold| threshold.trim().parse().ok())
+                .collect::<Option<_>>()?;
+            thresholds.try_into().ok()
+        })
+        .filter(|[warning, critical]: &[Decimal; 2]| {
+            Decimal::ZERO < *warning && warning <= critical
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "option {} expects a warning and a critical percentage, e.g 0.65,0.9, got {:?}",
+                option,
+                value
+            )
+        })
+}
+
+// Fetches a number of days following an option, returning it in seconds.
+fn days_of(option: &str, args: &mut impl Iterator<Item = OsString>) -> Result<u64> {
+    let value = value_of(option, args)?;
+    let days = value
+        .to_str()
+        .and_then(|days| days.parse::<u64>().ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "option {} expects a number of days, got {:?}",
+                option,
+                value
+            )
+        })?;
+    days.checked_mul(DAY).ok_or_else(|| {
+        anyhow!(
+            "option {} expects at most {} days, got {}",
+            option,
+            u64::MAX / DAY,
+            days
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Format, Options};
+    use crate::account::NegativeBalancePolicy;
+    use crate::clock::DAY;
+    use crate::engine::Cutoff;
+    use crate::statement::Period;
+    use rust_decimal::Decimal;
+    use std::ffi::OsString;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn the_csv_path_is_required() {
+        assert!(Options::parse(args(&[])).is_err());
+    }
+
+    #[test]
+    fn negative_chargebacks_are_rejected_unless_asked_for() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["tx.csv"]))?;
+        assert_eq!(options.csv_path, "tx.csv");
+        assert_eq!(
+            options.config.negative_balance_policy,
+            NegativeBalancePolicy::Reject
+        );
+        let options = Options::parse(args(&["--allow-negative-chargebacks", "tx.csv"]))?;
+        assert_eq!(
+            options.config.negative_balance_policy,
+            NegativeBalancePolicy::AllowOnChargeback
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn options_taking_a_value_fail_without_one() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["--overdraft-limits", "limits.csv", "tx.csv"]))?;
+        assert_eq!(options.overdraft_limits, Some("limits.csv".into()));
+        assert_eq!(options.csv_path, "tx.csv");
+        assert!(Options::parse(args(&["tx.csv", "--overdraft-limits"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn windows_are_given_in_days() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["--dispute-window-days", "120", "tx.csv"]))?;
+        assert_eq!(options.config.windows.dispute, Some(120 * DAY));
+        assert!(Options::parse(args(&["--dispute-window-days", "soon", "tx.csv"])).is_err());
+        let forever = u64::MAX.to_string();
+        assert!(Options::parse(args(&["--dispute-window-days", &forever, "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn risk_thresholds_are_three_increasing_scores() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["--risk-thresholds", "40, 60,90", "tx.csv"]))?;
+        assert_eq!(options.risk_thresholds, Some([40, 60, 90]));
+        assert!(Options::parse(args(&["--risk-thresholds", "40,60", "tx.csv"])).is_err());
+        assert!(Options::parse(args(&["--risk-thresholds", "90,60,40", "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_ratio_options_need_thresholds() -> anyhow::Result<()> {
+        let options = Options::parse(args(&[
+            "--chargeback-ratio",
+            "0.65,0.9",
+            "--chargeback-ratio-days",
+            "7",
+            "tx.csv",
+        ]))?;
+        let policy = options
+            .config
+            .chargeback_ratio
+            .expect("a chargeback ratio policy");
+        assert_eq!(policy.critical, Decimal::new(9, 1));
+        assert_eq!(policy.window, 7 * DAY);
+        assert!(!policy.lock);
+        assert!(Options::parse(args(&["--lock-on-critical-ratio", "tx.csv"])).is_err());
+        assert!(Options::parse(args(&["--chargeback-ratio", "0.9,0.65", "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deposits_are_cached_in_memory_by_default() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["tx.csv"]))?;
+        assert_eq!(options.deposit_store, None);
+        let options = Options::parse(args(&[
+            "--deposit-store",
+            "deposits.db",
+            "--deposit-cache",
+            "10",
+            "tx.csv",
+        ]))?;
+        assert_eq!(options.deposit_store, Some("deposits.db".into()));
+        assert_eq!(options.deposit_cache, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn the_history_command_needs_a_client() -> anyhow::Result<()> {
+        assert_eq!(
+            Options::parse(args(&["tx.csv"]))?.command,
+            Command::Accounts
+        );
+        let options = Options::parse(args(&["history", "--client", "7", "tx.csv"]))?;
+        assert_eq!(options.command, Command::History { client: 7 });
+        assert_eq!(options.csv_path, "tx.csv");
+        assert!(Options::parse(args(&["history", "tx.csv"])).is_err());
+        assert!(Options::parse(args(&["history", "--client", "x", "tx.csv"])).is_err());
+        let options = Options::parse(args(&["explain", "tx.csv", "--client", "7"]))?;
+        assert_eq!(options.command, Command::Explain { client: 7 });
+        let options = Options::parse(args(&["case", "--client", "7", "--tx", "3", "tx.csv"]))?;
+        assert_eq!(options.command, Command::Case { client: 7, tx: 3 });
+        assert!(Options::parse(args(&["case", "--client", "7", "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn balances_are_as_of_a_timestamp_or_a_row() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["balances", "--as-of", "86400", "tx.csv"]))?;
+        assert_eq!(
+            options.command,
+            Command::Balances {
+                as_of: Cutoff::Timestamp(86400)
+            }
+        );
+        let options = Options::parse(args(&["balances", "--as-of", "row:3", "tx.csv"]))?;
+        assert_eq!(
+            options.command,
+            Command::Balances {
+                as_of: Cutoff::Row(3)
+            }
+        );
+        assert!(Options::parse(args(&["balances", "tx.csv"])).is_err());
+        assert!(Options::parse(args(&["balances", "--as-of", "row:x", "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn statements_are_for_a_period_in_a_format() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["statement", "tx.csv"]))?;
+        assert_eq!(
+            options.command,
+            Command::Statement {
+                client: None,
+                period: Period::default(),
+                format: Format::Text,
+            }
+        );
+        let options = Options::parse(args(&[
+            "statement",
+            "--client",
+            "2",
+            "--from",
+            "10",
+            "--to",
+            "20",
+            "--format",
+            "html",
+            "tx.csv",
+        ]))?;
+        assert_eq!(
+            options.command,
+            Command::Statement {
+                client: Some(2),
+                period: Period {
+                    from: Some(10),
+                    to: Some(20)
+                },
+                format: Format::Html,
+            }
+        );
+        assert!(Options::parse(args(&["statement", "--format", "pdf", "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reconciling_needs_something_to_reconcile_against() -> anyhow::Result<()> {
+        let options = Options::parse(args(&[
+            "reconcile",
+            "--against",
+            "bank.csv",
+            "--tolerance",
+            "0.01",
+            "tx.csv",
+        ]))?;
+        assert_eq!(
+            options.command,
+            Command::Reconcile {
+                against: "bank.csv".into(),
+                tolerance: Decimal::new(1, 2),
+            }
+        );
+        assert!(Options::parse(args(&["reconcile", "tx.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn client_tiers_need_something_to_be_tiered() -> anyhow::Result<()> {
+        assert!(Options::parse(args(&["--client-tiers", "tiers.csv", "tx.csv"])).is_err());
+        for tiered in ["--fees", "--interest-rates"] {
+            let options = Options::parse(args(&[
+                "--client-tiers",
+                "tiers.csv",
+                tiered,
+                "x.csv",
+                "tx.csv",
+            ]))?;
+            assert_eq!(options.client_tiers, Some("tiers.csv".into()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn diffing_takes_two_files_of_accounts() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["diff", "before.csv", "after.csv"]))?;
+        assert_eq!(
+            options.command,
+            Command::Diff {
+                before: "before.csv".into(),
+                after: "after.csv".into(),
+            }
+        );
+        assert!(Options::parse(args(&["diff", "before.csv"])).is_err());
+        assert!(Options::parse(args(&["tx.csv", "after.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn a_dry_run_replaces_the_accounts() -> anyhow::Result<()> {
+        let options = Options::parse(args(&["--dry-run", "fixes.csv", "tx.csv"]))?;
+        assert_eq!(options.dry_run, Some("fixes.csv".into()));
+        assert!(Options::parse(args(&[
+            "explain",
+            "--client",
+            "1",
+            "--dry-run",
+            "fixes.csv",
+            "tx.csv"
+        ]))
+        .is_err());
+        Ok(())
+    }
+}
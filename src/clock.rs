@@ -0,0 +1,80 @@
+// Time in paperstack is whatever the input says it is: seconds since the unix
+// epoch as given by the optional timestamp column. Wall time is never used so
+// that processing the same input always gives the same result.
+pub type Timestamp = u64;
+
+pub const DAY: u64 = 24 * 60 * 60;
+
+// The engine clock only ever moves forward. Transactions without a timestamp,
+// or with one earlier than what we've already seen, happen "now".
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    now: Option<Timestamp>,
+}
+
+impl Clock {
+    pub fn now(&self) -> Option<Timestamp> {
+        self.now
+    }
+    // Moves the clock forward to the given time. Returns true if that took the
+    // clock into a new day.
+    pub fn advance(&mut self, to: Timestamp) -> bool {
+        match self.now {
+            Some(now) if now >= to => false,
+            Some(now) => {
+                self.now = Some(to);
+                now / DAY != to / DAY
+            }
+            None => {
+                self.now = Some(to);
+                true
+            }
+        }
+    }
+}
+
+// How long things may stay around, in seconds. No window means forever.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Windows {
+    // Disputes arriving later than this after the deposit are rejected.
+    pub dispute: Option<u64>,
+    // Deposits older than this are no longer kept around for disputes.
+    pub retention: Option<u64>,
+    // Authorizations not captured within this are released.
+    pub authorization: Option<u64>,
+}
+
+// Whether something that happened at `then` is more than `window` ago. If we
+// don't know when it happened, or what time it is, it never is.
+pub fn has_elapsed(then: Option<Timestamp>, now: Option<Timestamp>, window: Option<u64>) -> bool {
+    match (then, now, window) {
+        (Some(then), Some(now), Some(window)) => now.saturating_sub(then) > window,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_elapsed, Clock, DAY};
+
+    #[test]
+    fn the_clock_never_moves_backwards() {
+        let mut clock = Clock::default();
+        assert_eq!(clock.now(), None);
+        assert!(clock.advance(10));
+        assert!(!clock.advance(5));
+        assert_eq!(clock.now(), Some(10));
+        assert!(!clock.advance(20));
+        assert!(clock.advance(DAY + 1));
+        assert_eq!(clock.now(), Some(DAY + 1));
+    }
+
+    #[test]
+    fn a_window_only_elapses_when_all_times_are_known() {
+        assert!(has_elapsed(Some(0), Some(DAY + 1), Some(DAY)));
+        assert!(!has_elapsed(Some(0), Some(DAY), Some(DAY)));
+        assert!(!has_elapsed(None, Some(DAY + 1), Some(DAY)));
+        assert!(!has_elapsed(Some(0), None, Some(DAY)));
+        assert!(!has_elapsed(Some(0), Some(DAY + 1), None));
+    }
+}
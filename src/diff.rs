@@ -0,0 +1,151 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt;
+
+use crate::account::serialize_with_fixed_digits;
+use crate::snapshot::Change;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Added,
+    Removed,
+    Changed,
+}
+
+// A change to a client's balances flattened into a csv row, with the amounts
+// as after less before.
+#[derive(Serialize, Debug)]
+pub struct DiffRow {
+    client: u16,
+    change: Kind,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    available: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    held: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    total: Decimal,
+    locked_before: Option<bool>,
+    locked_after: Option<bool>,
+}
+
+fn kind_of(change: &Change) -> Kind {
+    match (change.before, change.after) {
+        (None, _) => Kind::Added,
+        (_, None) => Kind::Removed,
+        _ => Kind::Changed,
+    }
+}
+
+impl From<&Change> for DiffRow {
+    fn from(change: &Change) -> Self {
+        DiffRow {
+            client: change.client,
+            change: kind_of(change),
+            available: change.available,
+            held: change.held,
+            total: change.total,
+            locked_before: change.before.map(|balances| balances.locked),
+            locked_after: change.after.map(|balances| balances.locked),
+        }
+    }
+}
+
+// Totals over every change between two sets of balances.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    // How many clients were locked or unlocked.
+    pub locked: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    // The client with the largest change to any one amount, and that change.
+    pub largest: Option<(u16, Decimal)>,
+}
+
+impl Summary {
+    pub fn new(changes: &[Change]) -> Self {
+        let mut summary = Summary::default();
+        for change in changes {
+            match kind_of(change) {
+                Kind::Added => summary.added += 1,
+                Kind::Removed => summary.removed += 1,
+                Kind::Changed => summary.changed += 1,
+            }
+            if change.locked_changed() {
+                summary.locked += 1;
+            }
+            summary.available += change.available;
+            summary.held += change.held;
+            summary.total += change.total;
+            let amount = change.largest_amount();
+            if summary.largest.is_none_or(|(_, largest)| amount > largest) {
+                summary.largest = Some((change.client, amount));
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed, {} locked or unlocked",
+            self.added, self.removed, self.changed, self.locked
+        )?;
+        writeln!(
+            f,
+            "net change: available {:.4}, held {:.4}, total {:.4}",
+            self.available, self.held, self.total
+        )?;
+        if let Some((client, amount)) = self.largest {
+            writeln!(f, "largest change: {:.4} for client {}", amount, client)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiffRow, Kind, Summary};
+    use crate::snapshot::{balances_after, changes, deposit};
+    use crate::Transaction;
+    use anyhow::Result;
+
+    #[test]
+    fn a_summary_totals_every_change() -> Result<()> {
+        let before = balances_after(vec![
+            deposit(1, 10.into()),
+            deposit(2, 10.into()),
+            deposit(3, 10.into()),
+        ])?;
+        let after = balances_after(vec![
+            deposit(1, 10.into()),
+            deposit(2, 10.into()),
+            Transaction::Withdrawal {
+                client: 2,
+                tx: 5,
+                amount: Some(6.into()),
+            },
+            Transaction::Lock {
+                client: 2,
+                tx: 5,
+                amount: None,
+            },
+            deposit(4, 1.into()),
+        ])?;
+        let changes = changes(&before, &after);
+        assert_eq!(DiffRow::from(&changes[0]).change, Kind::Changed);
+        assert_eq!(DiffRow::from(&changes[2]).change, Kind::Added);
+        let summary = Summary::new(&changes);
+        assert_eq!((summary.added, summary.removed, summary.changed), (1, 1, 1));
+        assert_eq!(summary.locked, 1);
+        assert_eq!(summary.total, (-15).into());
+        assert_eq!(summary.largest, Some((3, 10.into())));
+        Ok(())
+    }
+}
@@ -0,0 +1,1938 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    rc::Rc,
+};
+
+use crate::account::{Account, Balances, NegativeBalancePolicy};
+use crate::aml::{AmlRules, Monitor, Report};
+use crate::blocklist::{self, Blocked, Blocklist, Listing};
+use crate::case::{Case, CaseLine, Deadlines};
+use crate::clock::{Clock, Timestamp, Windows, DAY};
+use crate::fee::FeeSchedule;
+use crate::history::{BalanceChange, HistoryEntry, HistoryScope, Outcome};
+use crate::interest::InterestPolicy;
+use crate::ledger::{JournalLine, Ledger};
+use crate::limit::{Limits, Usage};
+use crate::overdraft::Overdraft;
+use crate::ratio::{Level, RatioLine, RatioPolicy};
+use crate::reserve::Reserve;
+use crate::risk::{Action, Alert, Assessment, Context, HeldForReview, RiskPolicy, RiskRejected};
+use crate::store::{DepositStore, MemoryStore, Overlay};
+use crate::transaction::TimedTransaction;
+use crate::Transaction;
+
+// Settings that apply to the accounts the engine creates.
+#[derive(Default, Debug, Clone)]
+pub struct Config {
+    pub negative_balance_policy: NegativeBalancePolicy,
+    // Approved credit lines by client. Clients not in here have none.
+    pub overdrafts: HashMap<u16, Overdraft>,
+    // Rolling reserves by client. Clients not in here have none.
+    pub reserves: HashMap<u16, Reserve>,
+    pub windows: Windows,
+    pub fees: Rc<FeeSchedule>,
+    pub limits: Rc<Limits>,
+    pub risk: Rc<RiskPolicy>,
+    // What compliance has to be told about, if anything.
+    pub aml_rules: Rc<AmlRules>,
+    // Chargeback ratio monitoring, if any.
+    pub chargeback_ratio: Option<RatioPolicy>,
+    // How long dispute cases may stay in each stage.
+    pub case_deadlines: Deadlines,
+    // Interest earned on available funds, if any.
+    pub interest: Option<InterestPolicy>,
+    pub history: HistoryScope,
+    // Whether to keep track of balance changes until they are taken.
+    pub balance_changes: bool,
+    // Whether to keep every ledger posting until it is taken.
+    pub journal: bool,
+    // Where to stop taking transactions, if anywhere.
+    pub as_of: Option<Cutoff>,
+}
+
+// A point in the input, either a (1-based) row or a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cutoff {
+    Row(u64),
+    Timestamp(Timestamp),
+}
+
+impl Cutoff {
+    // Whether the given row, happening at the given time, comes after the cutoff.
+    fn is_passed_by(&self, row: u64, timestamp: Option<Timestamp>) -> bool {
+        match (self, timestamp) {
+            (Cutoff::Row(cutoff), _) => row > *cutoff,
+            (Cutoff::Timestamp(cutoff), Some(timestamp)) => timestamp > *cutoff,
+            (Cutoff::Timestamp(_), None) => false,
+        }
+    }
+}
+
+// The error a transaction is turned away with once the engine has reached its
+// cutoff, so that it can be told apart from rejections by downcasting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PastCutoff;
+
+impl fmt::Display for PastCutoff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "past the cutoff")
+    }
+}
+
+impl Error for PastCutoff {}
+
+// The engine keeps track of all accounts and routes every transaction to the
+// account it belongs to, creating accounts as needed.
+#[derive(Debug)]
+pub struct Engine<'a> {
+    config: Config,
+    accounts: HashMap<u16, Account>,
+    deposits: Box<dyn DepositStore + 'a>,
+    clock: Clock,
+    ledger: Ledger,
+    // How many transactions have been given to the engine so far.
+    seq: u64,
+    changes: Vec<BalanceChange>,
+    // Ledger postings, until they are taken.
+    journal: Vec<JournalLine>,
+    // Limits withdrawals have taken clients near to, until they are taken.
+    warnings: Vec<Usage>,
+    // Alerts for risky transactions, until they are taken.
+    alerts: Vec<Alert>,
+    monitor: Monitor,
+    blocklist: Option<Blocklist>,
+    // Transactions held for review, by client and tx id, until a review_release
+    // with their tx id lets them through.
+    held: HashMap<(u16, u32), Transaction>,
+    // What the monitor found for compliance, until it is taken.
+    reports: Vec<Report>,
+    // What went wrong with what the engine did by itself as the day changed,
+    // until it is taken.
+    errors: Vec<anyhow::Error>,
+    // Days of interest accrued since it was last credited.
+    interest_days: u64,
+    // Whether the cutoff has been reached, after which nothing more is taken.
+    done: bool,
+}
+
+// How a transaction is screened before it is processed: assessed by the risk
+// rules, blocked by a listing of its client or let through once reviewed. What
+// the engine makes itself, like interest or releases, isn't screened at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Screening {
+    Risk,
+    Blocked(Listing),
+    Reviewed,
+    System,
+}
+
+// A transaction that would have been rejected, by its (1-based) row in the batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub row: u64,
+    pub transaction: Transaction,
+    pub reason: String,
+}
+
+// What applying a batch of transactions would have done.
+#[derive(Debug, Default)]
+pub struct Simulation {
+    // The balances of every existing client the batch touched, before the batch.
+    pub before: BTreeMap<u16, Balances>,
+    // The balances of every client the batch touched, after the batch.
+    pub after: BTreeMap<u16, Balances>,
+    pub rejections: Vec<Rejection>,
+    // What compliance would be told about, taking what came before the batch into account.
+    pub reports: Vec<Report>,
+}
+
+impl Default for Engine<'_> {
+    fn default() -> Self {
+        Engine::new(Config::default())
+    }
+}
+
+impl<'a> Engine<'a> {
+    // Deposits are kept in memory unless another store is given.
+    pub fn new(config: Config) -> Self {
+        Engine {
+            monitor: Monitor::new(config.aml_rules.clone()),
+            config,
+            accounts: HashMap::new(),
+            deposits: Box::new(MemoryStore::default()),
+            clock: Clock::default(),
+            ledger: Ledger::default(),
+            seq: 0,
+            changes: Vec::new(),
+            journal: Vec::new(),
+            warnings: Vec::new(),
+            alerts: Vec::new(),
+            blocklist: None,
+            held: HashMap::new(),
+            reports: Vec::new(),
+            errors: Vec::new(),
+            interest_days: 0,
+            done: false,
+        }
+    }
+
+    pub fn with_deposit_store(mut self, deposits: Box<dyn DepositStore + 'a>) -> Self {
+        self.deposits = deposits;
+        self
+    }
+
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    // Reads the blocklist again if it has been modified since it was last read.
+    pub fn reload_blocklist(&mut self) -> Result<bool> {
+        match &mut self.blocklist {
+            Some(blocklist) => blocklist.reload_if_modified(),
+            None => Ok(false),
+        }
+    }
+
+    // Applies a transaction, first moving the engine clock forward to its timestamp.
+    // The first transaction past the cutoff, if there is one, finishes the engine
+    // instead and it, like everything after it, is turned away with PastCutoff.
+    pub fn apply(&mut self, timed: TimedTransaction) -> Result<()> {
+        if self.done
+            || self
+                .config
+                .as_of
+                .is_some_and(|cutoff| cutoff.is_passed_by(self.seq + 1, timed.timestamp))
+        {
+            self.finish();
+            return Err(PastCutoff.into());
+        }
+        self.seq += 1;
+        if let Some(timestamp) = timed.timestamp {
+            self.advance(timestamp);
+        }
+        // Only transactions given to the engine are screened, not those it makes itself.
+        let listing = self
+            .blocklist
+            .as_ref()
+            .and_then(|blocklist| blocklist.get(*timed.transaction.get_client()))
+            .cloned();
+        self.process(
+            timed.transaction,
+            listing.map_or(Screening::Risk, Screening::Blocked),
+        )
+    }
+
+    // Brings the engine up to its cutoff once there is nothing more to apply. For
+    // a timestamp cutoff that means moving the clock on to it, so that whatever
+    // was due by then has happened, even if the input ends well before it.
+    pub fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        if let Some(Cutoff::Timestamp(cutoff)) = self.config.as_of {
+            self.advance(cutoff);
+        }
+    }
+
+    // Moves the engine clock forward. Whenever the clock enters a new day, interest
+    // is accrued for the days that have passed and deposits and authorizations that
+    // have outlived their windows are swept. Whatever goes wrong with that is no
+    // fault of the transaction that moved the clock, so it is kept apart (see
+    // take_errors) and the transaction is applied regardless.
+    fn advance(&mut self, timestamp: Timestamp) {
+        let then = self.clock.now();
+        if self.clock.advance(timestamp) {
+            let days = then.map_or(0, |then| timestamp / DAY - then / DAY);
+            self.accrue(days);
+            self.sweep();
+        }
+    }
+
+    // Processes a transaction for a client, screening it as told. A review_release
+    // of a held transaction processes that transaction instead, as reviewed.
+    fn process(&mut self, transaction: Transaction, screening: Screening) -> Result<()> {
+        if let Transaction::ReviewRelease { client, tx, .. } = transaction {
+            if let Some(held) = self.held.remove(&(client, tx)) {
+                return self.process(held, Screening::Reviewed);
+            }
+        }
+        // Here we're trying to either find an account with the correct client id or create a new one
+        // if one doesn't exist.
+        let client = *transaction.get_client();
+        let config = &self.config;
+        let account = self.accounts.entry(client).or_insert_with(|| {
+            let account = Account::new(client)
+                .with_negative_balance_policy(config.negative_balance_policy)
+                .with_windows(config.windows)
+                .with_fees(config.fees.clone())
+                .with_limits(config.limits.clone());
+            // Only with overdrafts or reserves configured is there an overdraft or
+            // reserved column, for every client.
+            let account = if config.overdrafts.is_empty() {
+                account
+            } else {
+                account.with_overdraft(config.overdrafts.get(&client).copied().unwrap_or_default())
+            };
+            if config.reserves.is_empty() {
+                account
+            } else {
+                account.with_reserve(config.reserves.get(&client).copied().unwrap_or_default())
+            }
+        });
+        let recorded = config.history.includes(client).then(|| transaction.clone());
+        let before = account.balances();
+        let (transaction_kind, transaction_tx) = (transaction.get_kind(), *transaction.get_tx());
+        // Risky transactions, and any transaction of a blocked client, are alerted
+        // on and may be rejected before they are applied.
+        let screened = !matches!(screening, Screening::Reviewed | Screening::System);
+        let listing = match screening {
+            Screening::Blocked(listing) => Some(listing),
+            _ => None,
+        };
+        let assessment = match (&listing, screened) {
+            (_, false) => None,
+            (Some(listing), _) => Some(Assessment {
+                score: 0,
+                action: match listing.action {
+                    blocklist::Action::Reject => Action::Reject,
+                    blocklist::Action::Hold => Action::Hold,
+                },
+                rules: vec!["blocklist"],
+            }),
+            (None, _) => config.risk.assess(&Context {
+                transaction: &transaction,
+                now: self.clock.now(),
+                balances: before,
+                activity: account.get_activity(),
+            }),
+        };
+        let observed = (config.risk.window().is_some()
+            || config.chargeback_ratio.is_some()
+            || !self.monitor.is_empty())
+        .then(|| transaction.clone());
+        let result = match (&assessment, listing) {
+            (Some(assessment), Some(listing)) if assessment.action == Action::Reject => {
+                Err(Blocked(listing).into())
+            }
+            (Some(assessment), None) if assessment.action == Action::Reject => Err(RiskRejected {
+                client,
+                tx: transaction_tx,
+                score: assessment.score,
+            }
+            .into()),
+            // Risky transactions to hold, and any transaction of a client listed to be
+            // held, are set aside untouched until reviewed.
+            (Some(assessment), listing) if assessment.action == Action::Hold => {
+                match self.held.entry((client, transaction_tx)) {
+                    Entry::Occupied(_) => Err(anyhow!(
+                        "client {}: tx {} is already held for review",
+                        client,
+                        transaction_tx
+                    )),
+                    Entry::Vacant(entry) => {
+                        entry.insert(transaction);
+                        Err(match listing {
+                            Some(listing) => Blocked(listing).into(),
+                            None => HeldForReview {
+                                client,
+                                tx: transaction_tx,
+                                score: assessment.score,
+                            }
+                            .into(),
+                        })
+                    }
+                }
+            }
+            _ => account.apply_timed_transaction(
+                TimedTransaction {
+                    transaction,
+                    timestamp: self.clock.now(),
+                },
+                self.deposits.as_mut(),
+            ),
+        };
+        if let (Ok(()), Some(transaction)) = (&result, observed) {
+            if let Some(window) = config.risk.window() {
+                account.observe(&transaction, self.clock.now(), window);
+            }
+            if let Some(policy) = config.chargeback_ratio {
+                account.tally(&transaction, self.clock.now(), policy.window);
+            }
+            let reports = self.monitor.observe(&transaction, self.clock.now());
+            self.reports.extend(reports);
+        }
+        // Whatever the account did, the ledger should know about it.
+        let postings = account.take_postings();
+        self.ledger.post(&postings)?;
+        if config.journal {
+            self.journal
+                .extend(postings.iter().map(|posting| JournalLine {
+                    seq: self.seq,
+                    timestamp: self.clock.now(),
+                    kind: transaction_kind,
+                    tx: transaction_tx,
+                    account: posting.account.name(),
+                    client: posting.account.client(),
+                    amount: posting.amount,
+                }));
+        }
+        self.warnings.extend(account.take_warnings());
+        let after = account.balances();
+        if config.balance_changes && after != before {
+            self.changes.push(BalanceChange {
+                client,
+                seq: self.seq,
+                timestamp: self.clock.now(),
+                kind: transaction_kind,
+                tx: transaction_tx,
+                available: after.available,
+                held: after.held,
+                total: after.total,
+                locked: after.locked,
+            });
+        }
+        if let Some(transaction) = recorded {
+            account.record(HistoryEntry {
+                seq: self.seq,
+                timestamp: self.clock.now(),
+                transaction,
+                outcome: match &result {
+                    Ok(()) => Outcome::Applied,
+                    Err(e) => Outcome::Rejected(e.to_string()),
+                },
+                before,
+                after,
+            });
+        }
+        // A dispute taking a client's chargeback ratio to critical may lock the account.
+        let lock = result.is_ok()
+            && transaction_kind == "dispute"
+            && !after.locked
+            && config.chargeback_ratio.is_some_and(|policy| {
+                policy.lock
+                    && policy
+                        .line(client, account.get_tally(), self.clock.now(), false)
+                        .is_some_and(|line| line.level == Level::Critical)
+            });
+        if let Some(assessment) = assessment {
+            self.alerts.push(Alert {
+                seq: self.seq,
+                timestamp: self.clock.now(),
+                client,
+                kind: transaction_kind,
+                tx: transaction_tx,
+                score: assessment.score,
+                action: assessment.action,
+                rules: assessment.rules.join(";"),
+            });
+        }
+        if lock {
+            self.process(
+                Transaction::Lock {
+                    client,
+                    tx: transaction_tx,
+                    amount: None,
+                },
+                Screening::System,
+            )?;
+        }
+        result
+    }
+
+    // Applies a batch of transactions on top of the current state without
+    // changing it. Accounts are copied as the batch touches them and deposits
+    // are written to an overlay of the deposit store, so the cost is in the size
+    // of the batch rather than the size of the state.
+    pub fn simulate(&self, batch: impl IntoIterator<Item = TimedTransaction>) -> Simulation {
+        let mut shadow = Engine {
+            config: self.config.clone(),
+            accounts: HashMap::new(),
+            deposits: Box::new(Overlay::new(self.deposits.as_ref())),
+            clock: self.clock,
+            // Postings only ever add to the balances, which nothing reads while
+            // applying, so the shadow ledger only needs the batch's own postings.
+            ledger: Ledger::default(),
+            seq: self.seq,
+            changes: Vec::new(),
+            journal: Vec::new(),
+            warnings: Vec::new(),
+            alerts: Vec::new(),
+            monitor: self.monitor.clone(),
+            blocklist: self.blocklist.clone(),
+            held: self.held.clone(),
+            reports: Vec::new(),
+            errors: Vec::new(),
+            interest_days: self.interest_days,
+            // Finishing the input doesn't stop a batch being tried out on top of it.
+            done: false,
+        };
+        let interest = self.config.interest.as_ref();
+        let mut simulation = Simulation::default();
+        for (row, timed) in (1..).zip(batch) {
+            // Besides the client of the transaction, moving the clock on may
+            // touch any account with authorizations about to expire or reserves due,
+            // as well as any account accruing interest, which may be credited.
+            let mut touched = vec![*timed.transaction.get_client()];
+            let mut clock = shadow.clock;
+            if timed
+                .timestamp
+                .is_some_and(|timestamp| clock.advance(timestamp))
+            {
+                touched.extend(
+                    self.accounts
+                        .values()
+                        .filter(|account| {
+                            !account.expired_authorizations(clock.now()).is_empty()
+                                || !account.due_reserves(clock.now()).is_empty()
+                                || interest.is_some_and(|interest| {
+                                    !account.get_accrued_interest().is_zero()
+                                        || !interest
+                                            .daily_rate(
+                                                *account.get_client(),
+                                                account.get_available(),
+                                            )
+                                            .is_zero()
+                                })
+                        })
+                        .map(|account| *account.get_client()),
+                );
+            }
+            for client in touched {
+                if let (Entry::Vacant(entry), Some(account)) =
+                    (shadow.accounts.entry(client), self.accounts.get(&client))
+                {
+                    simulation.before.insert(client, account.balances());
+                    entry.insert(account.clone());
+                }
+            }
+            let transaction = timed.transaction.clone();
+            if let Err(e) = shadow.apply(timed) {
+                simulation.rejections.push(Rejection {
+                    row,
+                    transaction,
+                    reason: e.to_string(),
+                });
+            }
+        }
+        simulation.reports = shadow.take_reports();
+        simulation.after = shadow
+            .accounts
+            .values()
+            .map(|account| (*account.get_client(), account.balances()))
+            .collect();
+        simulation
+    }
+
+    // Accrues interest on every account for the days that have passed, on the
+    // balances they ended those days with. Every so many days whole cents of
+    // what has been accrued are credited, as if an interest transaction had come
+    // in, with the rest carried over. A credit failing doesn't stop the others.
+    fn accrue(&mut self, days: u64) {
+        let Some(interest) = &self.config.interest else {
+            return;
+        };
+        for account in self.accounts.values_mut() {
+            let rate = interest.daily_rate(*account.get_client(), account.get_available());
+            account.accrue_interest(rate, days);
+        }
+        self.interest_days += days;
+        if self.interest_days < interest.posting_days {
+            return;
+        }
+        self.interest_days = 0;
+        let mut credits: Vec<_> = self
+            .accounts
+            .values()
+            .map(|account| {
+                let accrued = account.get_accrued_interest();
+                let cents = accrued.round_dp_with_strategy(2, RoundingStrategy::ToZero);
+                (*account.get_client(), cents)
+            })
+            .filter(|(_, cents)| *cents > Decimal::ZERO)
+            .collect();
+        credits.sort_unstable_by_key(|(client, _)| *client);
+        for (client, amount) in credits {
+            let credit = Transaction::Interest {
+                client,
+                tx: 0,
+                amount: Some(amount),
+            };
+            if let Err(e) = self.process(credit, Screening::System) {
+                self.errors.push(e);
+            }
+        }
+    }
+
+    // Expired authorizations are released just as if a release had come in, so
+    // that they are recorded like any other transaction. The same goes for
+    // reserves that are due. One thing failing doesn't stop the others.
+    fn sweep(&mut self) {
+        let now = self.clock.now();
+        if let Err(e) = self.deposits.sweep(now, self.config.windows.retention) {
+            self.errors.push(e);
+        }
+        let mut expired = Vec::new();
+        for (client, account) in &self.accounts {
+            for tx in account.expired_authorizations(now) {
+                expired.push(Transaction::Release {
+                    client: *client,
+                    tx,
+                    amount: None,
+                });
+            }
+            for tx in account.due_reserves(now) {
+                expired.push(Transaction::ReserveRelease {
+                    client: *client,
+                    tx,
+                    amount: None,
+                });
+            }
+        }
+        for release in expired {
+            if let Err(e) = self.process(release, Screening::System) {
+                self.errors.push(e);
+            }
+        }
+    }
+
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    // Balance changes since they were last taken, in the order they happened.
+    pub fn take_changes(&mut self) -> Vec<BalanceChange> {
+        std::mem::take(&mut self.changes)
+    }
+
+    // Ledger postings since they were last taken, in the order they were made.
+    pub fn take_journal(&mut self) -> Vec<JournalLine> {
+        std::mem::take(&mut self.journal)
+    }
+
+    // What went wrong as the day changed since it was last taken.
+    pub fn take_errors(&mut self) -> Vec<anyhow::Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Usage> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+
+    pub fn take_reports(&mut self) -> Vec<Report> {
+        std::mem::take(&mut self.reports)
+    }
+
+    // Adds a note to the client's case of the deposit tx, at the engine time.
+    pub fn note(&mut self, client: u16, tx: u32, text: String) -> Result<()> {
+        let now = self.clock.now();
+        match self.accounts.get_mut(&client) {
+            Some(account) => account.note_case(tx, now, text),
+            None => Err(anyhow!("there is no case {} to note", tx)),
+        }
+    }
+
+    pub fn case(&self, client: u16, tx: u32) -> Option<&Case> {
+        self.accounts.get(&client)?.case(tx)
+    }
+
+    // Every dispute case as of the engine time, by client and case.
+    pub fn case_report(&self) -> Vec<CaseLine> {
+        let mut lines: Vec<_> = self
+            .accounts
+            .values()
+            .flat_map(|account| account.cases())
+            .map(|case| case.line(&self.config.case_deadlines, self.clock.now()))
+            .collect();
+        lines.sort_by_key(|line| (line.client, line.case));
+        lines
+    }
+
+    // Every client over a chargeback ratio threshold as of the engine time, by client.
+    pub fn ratio_report(&self) -> Vec<RatioLine> {
+        let policy = match self.config.chargeback_ratio {
+            Some(policy) => policy,
+            None => return Vec::new(),
+        };
+        let mut lines: Vec<_> = self
+            .accounts
+            .iter()
+            .filter_map(|(client, account)| {
+                let locked = account.balances().locked;
+                policy.line(*client, account.get_tally(), self.clock.now(), locked)
+            })
+            .collect();
+        lines.sort_by_key(|line| line.client);
+        lines
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    // Checks that the ledger balances and agrees with every account.
+    pub fn verify(&self) -> Result<()> {
+        self.ledger.verify(self.accounts.values())
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Cutoff, Engine, PastCutoff};
+    use crate::account::NegativeBalancePolicy;
+    use crate::aml::{AmlRules, Rule};
+    use crate::blocklist::{self, Blocked, Blocklist, Listing};
+    use crate::case::{Deadlines, Stage};
+    use crate::clock::{Windows, DAY};
+    use crate::history::{HistoryScope, Outcome};
+    use crate::interest::{InterestPolicy, RateBand};
+    use crate::ledger::LedgerAccount;
+    use crate::limit::{Limit, LimitExceeded, Limits, Measure};
+    use crate::overdraft::{Overdraft, OverdraftMode};
+    use crate::ratio::{Level, RatioPolicy};
+    use crate::reserve::Reserve;
+    use crate::risk::{
+        Action, Context, HeldForReview, LargeWithdrawalAfterDeposit, RapidDisputes, RiskPolicy,
+        RiskRejected, RiskRule,
+    };
+    use crate::store::{DepositStore, MemoryStore};
+    use crate::transaction::TimedTransaction;
+    use crate::Transaction;
+    use anyhow::{anyhow, Result};
+    use rust_decimal::Decimal;
+    use std::{collections::HashMap, rc::Rc};
+
+    // A transaction that happens at the current engine time.
+    fn now(transaction: Transaction) -> TimedTransaction {
+        TimedTransaction {
+            transaction,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn transactions_are_routed_to_the_account_of_their_client() -> Result<()> {
+        let mut engine = Engine::default();
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        }))?;
+        engine.apply(now(Transaction::Deposit {
+            client: 2,
+            tx: 2,
+            amount: Some(20.into()),
+        }))?;
+        assert_eq!(engine.accounts().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn new_accounts_get_the_configured_negative_balance_policy() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            negative_balance_policy: NegativeBalancePolicy::AllowOnChargeback,
+            ..Default::default()
+        });
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        }))?;
+        engine.apply(now(Transaction::Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: Some(10.into()),
+        }))?;
+        engine.apply(now(Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        }))?;
+        assert!(engine
+            .apply(now(Transaction::Chargeback {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn new_accounts_get_their_configured_overdraft() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            overdrafts: HashMap::from([(
+                1,
+                Overdraft {
+                    limit: 10.into(),
+                    mode: OverdraftMode::InterestFree,
+                },
+            )]),
+            ..Default::default()
+        });
+        engine.apply(now(Transaction::Withdrawal {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        }))?;
+        assert!(engine
+            .apply(now(Transaction::Withdrawal {
+                client: 2,
+                tx: 2,
+                amount: Some(10.into()),
+            }))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn transactions_without_a_timestamp_happen_at_the_engine_time() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            windows: Windows {
+                dispute: Some(DAY),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(0),
+        })?;
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 2,
+                tx: 2,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(2 * DAY),
+        })?;
+        // The engine clock is now 2 days past the first deposit.
+        assert!(engine
+            .apply(now(Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn every_applied_transaction_is_posted_to_the_ledger() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            negative_balance_policy: NegativeBalancePolicy::AllowOnChargeback,
+            ..Default::default()
+        });
+        for transaction in [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(100.into()),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(30.into()),
+            },
+            Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            Transaction::Chargeback {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+        ] {
+            let _ = engine.apply(now(transaction));
+        }
+        engine.verify()?;
+        let ledger = engine.ledger();
+        assert_eq!(
+            ledger.balance(LedgerAccount::ExternalSettlement),
+            (-70).into()
+        );
+        assert_eq!(ledger.balance(LedgerAccount::ChargebackLoss), 100.into());
+        assert_eq!(
+            ledger.balance(LedgerAccount::ClientAvailable(1)),
+            (-30).into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expired_authorizations_are_released_when_the_day_changes() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            windows: Windows {
+                authorization: Some(DAY),
+                ..Default::default()
+            },
+            history: HistoryScope::Client(1),
+            ..Default::default()
+        });
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(0),
+        })?;
+        engine.apply(now(Transaction::Authorize {
+            client: 1,
+            tx: 2,
+            amount: Some(10.into()),
+        }))?;
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 2,
+                tx: 3,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(2 * DAY),
+        })?;
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 10.into());
+        let history = account.get_history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].seq, 3);
+        assert_eq!(history[2].transaction.get_kind(), "release");
+        engine.verify()
+    }
+
+    #[test]
+    fn nothing_after_a_row_cutoff_is_applied() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            as_of: Some(Cutoff::Row(2)),
+            ..Default::default()
+        });
+        let deposit = |tx, timestamp| TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx,
+                amount: Some(10.into()),
+            },
+            timestamp,
+        };
+        engine.apply(deposit(1, Some(DAY)))?;
+        // A rejected row counts all the same.
+        assert!(engine
+            .apply(now(Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(100.into()),
+            }))
+            .is_err());
+        for tx in [3, 4] {
+            let e = engine
+                .apply(deposit(tx, None))
+                .expect_err("to be past the cutoff");
+            assert!(e.is::<PastCutoff>());
+        }
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 10.into());
+        engine.verify()
+    }
+
+    #[test]
+    fn a_timestamp_cutoff_brings_the_clock_up_to_it() -> Result<()> {
+        let config = Config {
+            windows: Windows {
+                authorization: Some(DAY),
+                ..Default::default()
+            },
+            as_of: Some(Cutoff::Timestamp(2 * DAY)),
+            ..Default::default()
+        };
+        let transactions = [
+            TimedTransaction {
+                transaction: Transaction::Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: Some(10.into()),
+                },
+                timestamp: Some(0),
+            },
+            now(Transaction::Authorize {
+                client: 1,
+                tx: 2,
+                amount: Some(10.into()),
+            }),
+            TimedTransaction {
+                transaction: Transaction::Deposit {
+                    client: 1,
+                    tx: 3,
+                    amount: Some(10.into()),
+                },
+                timestamp: Some(2 * DAY + 1),
+            },
+        ];
+        // The authorization has expired by the cutoff, whether or not anything
+        // comes after it.
+        for given in [3, 2] {
+            let mut engine = Engine::new(config.clone());
+            for timed in transactions.iter().take(given) {
+                if let Err(e) = engine.apply(timed.clone()) {
+                    assert!(e.is::<PastCutoff>());
+                }
+            }
+            engine.finish();
+            let account = engine.account(1).expect("account 1 to exist");
+            assert_eq!(account.balances().available, 10.into());
+            assert_eq!(account.balances().held, 0.into());
+            engine.verify()?;
+        }
+        Ok(())
+    }
+
+    // A deposit store that can't be swept, e.g as its disk has gone away.
+    #[derive(Debug, Default)]
+    struct Unsweepable(MemoryStore);
+
+    impl DepositStore for Unsweepable {
+        fn get(&mut self, tx: u32) -> Result<Option<TimedTransaction>> {
+            self.0.get(tx)
+        }
+        fn peek(&self, tx: u32) -> Result<Option<TimedTransaction>> {
+            self.0.peek(tx)
+        }
+        fn insert(&mut self, tx: u32, deposit: TimedTransaction) -> Result<()> {
+            self.0.insert(tx, deposit)
+        }
+        fn sweep(&mut self, _: Option<u64>, _: Option<u64>) -> Result<()> {
+            Err(anyhow!("deposit store gone"))
+        }
+    }
+
+    #[test]
+    fn a_failed_sweep_is_kept_apart_from_the_transaction_that_triggered_it() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            windows: Windows {
+                authorization: Some(DAY),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with_deposit_store(Box::new(Unsweepable::default()));
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(0),
+        })?;
+        engine.apply(now(Transaction::Authorize {
+            client: 1,
+            tx: 2,
+            amount: Some(10.into()),
+        }))?;
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 2,
+                tx: 3,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(2 * DAY),
+        })?;
+        // Once as the clock started and once as it entered a new day.
+        let errors = engine.take_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].to_string(), "deposit store gone");
+        assert!(engine.take_errors().is_empty());
+        // Neither the deposit nor the release of the expired authorization was held up.
+        let balances = |client| engine.account(client).expect("account to exist").balances();
+        assert_eq!(balances(2).available, 10.into());
+        assert_eq!(balances(1).available, 10.into());
+        engine.verify()
+    }
+
+    // A deposit store whose disk fills up after so many writes.
+    #[derive(Debug, Default)]
+    struct Filling(MemoryStore, usize);
+
+    impl DepositStore for Filling {
+        fn get(&mut self, tx: u32) -> Result<Option<TimedTransaction>> {
+            self.0.get(tx)
+        }
+        fn peek(&self, tx: u32) -> Result<Option<TimedTransaction>> {
+            self.0.peek(tx)
+        }
+        fn insert(&mut self, tx: u32, deposit: TimedTransaction) -> Result<()> {
+            if self.1 == 0 {
+                return Err(anyhow!("deposit store full"));
+            }
+            self.1 -= 1;
+            self.0.insert(tx, deposit)
+        }
+        fn sweep(&mut self, now: Option<u64>, retention: Option<u64>) -> Result<()> {
+            self.0.sweep(now, retention)
+        }
+    }
+
+    #[test]
+    fn no_money_moves_for_a_transaction_the_store_fails_to_record() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            reserves: HashMap::from([(
+                1,
+                Reserve {
+                    percentage: 10.into(),
+                    days: 2,
+                },
+            )]),
+            ..Default::default()
+        })
+        .with_deposit_store(Box::new(Filling(MemoryStore::default(), 1)));
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(100.into()),
+            },
+            timestamp: Some(0),
+        })?;
+        for transaction in [
+            Transaction::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Some(10.into()),
+            },
+            Transaction::Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: Some(10.into()),
+            },
+            Transaction::Reversal {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+        ] {
+            assert!(engine.apply(now(transaction)).is_err());
+        }
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 90.into());
+        assert_eq!(account.balances().held, 10.into());
+        assert_eq!(account.holds().count(), 1);
+        assert_eq!(account.due_reserves(Some(2 * DAY)), vec![1]);
+        assert!(account.case(1).is_none());
+        engine.verify()
+    }
+
+    #[test]
+    fn history_records_applied_and_rejected_transactions_of_included_clients() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            history: HistoryScope::Client(1),
+            ..Default::default()
+        });
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        }))?;
+        assert!(engine
+            .apply(now(Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(20.into()),
+            }))
+            .is_err());
+        engine.apply(now(Transaction::Deposit {
+            client: 2,
+            tx: 3,
+            amount: Some(10.into()),
+        }))?;
+        let history = engine.account(1).expect("account 1 to exist").get_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].outcome, Outcome::Applied);
+        assert_eq!(history[0].before.available, 0.into());
+        assert_eq!(history[0].after.available, 10.into());
+        assert!(matches!(history[1].outcome, Outcome::Rejected(_)));
+        assert_eq!(history[1].before, history[1].after);
+        assert!(engine
+            .account(2)
+            .expect("account 2 to exist")
+            .get_history()
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn balance_changes_are_kept_until_taken() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            windows: Windows {
+                authorization: Some(DAY),
+                ..Default::default()
+            },
+            balance_changes: true,
+            ..Default::default()
+        });
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            timestamp: Some(0),
+        })?;
+        assert!(engine
+            .apply(now(Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(20.into()),
+            }))
+            .is_err());
+        engine.apply(now(Transaction::Authorize {
+            client: 1,
+            tx: 3,
+            amount: Some(4.into()),
+        }))?;
+        let changes = engine.take_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].seq, changes[0].tx), (1, 1));
+        assert_eq!(changes[0].available, 10.into());
+        assert_eq!((changes[1].seq, changes[1].kind), (3, "authorize"));
+        assert_eq!(changes[1].held, 4.into());
+        assert_eq!(changes[1].total, 10.into());
+        assert!(engine.take_changes().is_empty());
+        // Released authorizations are changes too.
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 2,
+                tx: 4,
+                amount: Some(1.into()),
+            },
+            timestamp: Some(2 * DAY),
+        })?;
+        let changes = engine.take_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].client, changes[0].kind), (1, "release"));
+        assert_eq!(changes[0].timestamp, Some(2 * DAY));
+        assert_eq!(changes[1].client, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn ledger_postings_are_kept_until_taken() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            journal: true,
+            ..Default::default()
+        });
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        }))?;
+        assert!(engine
+            .apply(now(Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                amount: Some(20.into()),
+            }))
+            .is_err());
+        engine.apply(now(Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        }))?;
+        let journal = engine.take_journal();
+        let lines: Vec<_> = journal
+            .iter()
+            .map(|line| (line.seq, line.kind, line.account, line.amount))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (1, "deposit", "external_settlement", (-10).into()),
+                (1, "deposit", "client_available", 10.into()),
+                (3, "dispute", "client_available", (-10).into()),
+                (3, "dispute", "client_held", 10.into()),
+            ]
+        );
+        assert_eq!(journal[3].client, Some(1));
+        assert!(engine.take_journal().is_empty());
+        engine.verify()
+    }
+
+    #[test]
+    fn a_simulation_leaves_the_engine_as_it_was() -> Result<()> {
+        let mut engine = Engine::default();
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        }))?;
+        engine.apply(now(Transaction::Deposit {
+            client: 2,
+            tx: 2,
+            amount: Some(10.into()),
+        }))?;
+        let simulation = engine.simulate([
+            now(Transaction::Deposit {
+                client: 3,
+                tx: 3,
+                amount: Some(5.into()),
+            }),
+            now(Transaction::Dispute {
+                client: 3,
+                tx: 3,
+                amount: None,
+            }),
+            now(Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }),
+            now(Transaction::Withdrawal {
+                client: 1,
+                tx: 4,
+                amount: Some(1.into()),
+            }),
+        ]);
+        assert_eq!(simulation.before.len(), 1);
+        assert_eq!(simulation.before[&1].available, 10.into());
+        assert_eq!(simulation.after[&1].held, 10.into());
+        assert_eq!(simulation.after[&3].held, 5.into());
+        assert_eq!(simulation.rejections.len(), 1);
+        assert_eq!(simulation.rejections[0].row, 4);
+        // None of it happened.
+        assert_eq!(engine.accounts().count(), 2);
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().held, 0.into());
+        assert!(engine
+            .apply(now(Transaction::Dispute {
+                client: 3,
+                tx: 3,
+                amount: None,
+            }))
+            .is_err());
+        engine.apply(now(Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        }))?;
+        engine.verify()
+    }
+
+    #[test]
+    fn a_simulation_reports_what_it_adds_to_deposits_seen_before() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            aml_rules: Rc::new(AmlRules::new(vec![Rule::Structuring {
+                threshold: 10000.into(),
+                margin: 10.into(),
+                count: 3,
+                window: DAY,
+            }])),
+            ..Default::default()
+        });
+        let deposit = |tx| {
+            now(Transaction::Deposit {
+                client: 1,
+                tx,
+                amount: Some(9500.into()),
+            })
+        };
+        engine.apply(deposit(1))?;
+        engine.apply(deposit(2))?;
+        assert!(engine.take_reports().is_empty());
+        let simulation = engine.simulate([deposit(3)]);
+        assert_eq!(simulation.reports.len(), 1);
+        assert_eq!(simulation.reports[0].txs, "1;2;3");
+        // The monitor of the engine itself hasn't seen the batch.
+        engine.apply(deposit(4))?;
+        assert_eq!(engine.take_reports()[0].txs, "1;2;4");
+        Ok(())
+    }
+
+    #[test]
+    fn a_simulation_credits_interest_to_accounts_the_batch_doesnt_touch() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            interest: Some(
+                InterestPolicy::new(vec![RateBand {
+                    tier: None,
+                    above: Decimal::ZERO,
+                    rate: Decimal::new(365, 1),
+                }])
+                .with_posting_days(2),
+            ),
+            ..Default::default()
+        });
+        let deposit = |client, tx, amount: i64, timestamp| TimedTransaction {
+            transaction: Transaction::Deposit {
+                client,
+                tx,
+                amount: Some(amount.into()),
+            },
+            timestamp: Some(timestamp),
+        };
+        engine.apply(deposit(1, 1, 1000, 0))?;
+        // Two days at 0.1% a day, credited as the second day begins.
+        let simulation = engine.simulate([deposit(2, 2, 1, DAY), deposit(2, 3, 1, 2 * DAY)]);
+        assert_eq!(simulation.before[&1].available, 1000.into());
+        assert_eq!(simulation.after[&1].available, 1002.into());
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 1000.into());
+        assert!(account.get_accrued_interest().is_zero());
+        engine.verify()
+    }
+
+    #[test]
+    fn interest_is_accrued_daily_and_credited_periodically() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            interest: Some(
+                InterestPolicy::new(vec![RateBand {
+                    tier: None,
+                    above: Decimal::ZERO,
+                    rate: Decimal::new(365, 1),
+                }])
+                .with_posting_days(10),
+            ),
+            ..Default::default()
+        });
+        let deposit = |client, tx, amount: i64, timestamp| TimedTransaction {
+            transaction: Transaction::Deposit {
+                client,
+                tx,
+                amount: Some(amount.into()),
+            },
+            timestamp: Some(timestamp),
+        };
+        engine.apply(deposit(1, 1, 1000, 0))?;
+        engine.apply(deposit(2, 2, 1, DAY))?;
+        // A day at 0.1% a day.
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.get_accrued_interest(), 1.into());
+        engine.apply(deposit(2, 3, 1, 10 * DAY))?;
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 1010.into());
+        assert!(account.get_accrued_interest().is_zero());
+        // Client 2 has accrued less than a cent, which is carried over.
+        let account = engine.account(2).expect("account 2 to exist");
+        assert_eq!(account.balances().available, 2.into());
+        assert_eq!(account.get_accrued_interest(), Decimal::new(9, 3));
+        assert_eq!(
+            engine.ledger().balance(LedgerAccount::InterestExpense),
+            (-10).into()
+        );
+        engine.verify()
+    }
+
+    #[test]
+    fn reserves_are_released_once_due() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            reserves: HashMap::from([(
+                1,
+                Reserve {
+                    percentage: 10.into(),
+                    days: 2,
+                },
+            )]),
+            ..Default::default()
+        });
+        let deposit = |client, tx, timestamp| TimedTransaction {
+            transaction: Transaction::Deposit {
+                client,
+                tx,
+                amount: Some(100.into()),
+            },
+            timestamp: Some(timestamp),
+        };
+        engine.apply(deposit(1, 1, 0))?;
+        engine.apply(deposit(1, 2, DAY))?;
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().held, 20.into());
+        engine.apply(deposit(2, 3, 2 * DAY))?;
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 190.into());
+        assert_eq!(account.balances().held, 10.into());
+        engine.verify()
+    }
+
+    #[test]
+    fn withdrawals_beyond_a_limit_are_rejected_with_their_own_error() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            limits: Rc::new(Limits::new(vec![Limit {
+                client: None,
+                days: 1,
+                measure: Measure::Count,
+                max: 2.into(),
+                warn_at: 50.into(),
+            }])),
+            ..Default::default()
+        });
+        let at = |transaction, timestamp| TimedTransaction {
+            transaction,
+            timestamp: Some(timestamp),
+        };
+        let withdrawal = |tx| Transaction::Withdrawal {
+            client: 1,
+            tx,
+            amount: Some(10.into()),
+        };
+        engine.apply(at(
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(100.into()),
+            },
+            0,
+        ))?;
+        engine.apply(at(withdrawal(2), 0))?;
+        let warnings = engine.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].measure, Measure::Count);
+        engine.apply(at(withdrawal(3), 0))?;
+        let e = engine.apply(at(withdrawal(4), 0)).unwrap_err();
+        assert!(e.downcast_ref::<LimitExceeded>().is_some());
+        // Rejected withdrawals don't count, and a day later the window has moved on.
+        engine.apply(at(withdrawal(5), DAY + 1))?;
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 70.into());
+        // Captures count as withdrawals, while a refunded withdrawal no longer counts.
+        engine.apply(at(
+            Transaction::Authorize {
+                client: 1,
+                tx: 6,
+                amount: Some(10.into()),
+            },
+            DAY + 1,
+        ))?;
+        engine.apply(at(
+            Transaction::Capture {
+                client: 1,
+                tx: 6,
+                amount: Some(5.into()),
+            },
+            DAY + 1,
+        ))?;
+        let e = engine.apply(at(withdrawal(7), DAY + 1)).unwrap_err();
+        assert!(e.downcast_ref::<LimitExceeded>().is_some());
+        let capture = at(
+            Transaction::Capture {
+                client: 1,
+                tx: 6,
+                amount: None,
+            },
+            DAY + 1,
+        );
+        let e = engine.apply(capture.clone()).unwrap_err();
+        assert!(e.downcast_ref::<LimitExceeded>().is_some());
+        engine.apply(at(
+            Transaction::Refund {
+                client: 1,
+                tx: 5,
+                amount: None,
+            },
+            DAY + 1,
+        ))?;
+        engine.apply(capture)?;
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 70.into());
+        assert_eq!(account.balances().held, 0.into());
+        engine.verify()
+    }
+
+    #[test]
+    fn risky_transactions_are_alerted_on_and_held_or_rejected() -> Result<()> {
+        let risk = RiskPolicy::default()
+            .with_rule(Box::new(LargeWithdrawalAfterDeposit {
+                days: 1,
+                percentage: 50.into(),
+                score: 80,
+            }))
+            .with_rule(Box::new(RapidDisputes {
+                days: 1,
+                count: 2,
+                score: 100,
+            }));
+        let mut engine = Engine::new(Config {
+            risk: Rc::new(risk),
+            ..Default::default()
+        });
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(100.into()),
+        }))?;
+        engine.apply(now(Transaction::Deposit {
+            client: 1,
+            tx: 2,
+            amount: Some(100.into()),
+        }))?;
+        // Withdrawing half of what came in scores enough to be held for review,
+        // leaving the account untouched until the withdrawal is released.
+        let e = engine
+            .apply(now(Transaction::Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: Some(100.into()),
+            }))
+            .unwrap_err();
+        assert!(e.downcast_ref::<HeldForReview>().is_some());
+        let alerts = engine.take_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].action, Action::Hold);
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 200.into());
+        assert_eq!(account.balances().held, Decimal::ZERO);
+        engine.apply(now(Transaction::ReviewRelease {
+            client: 1,
+            tx: 3,
+            amount: None,
+        }))?;
+        assert!(engine.take_alerts().is_empty());
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, 100.into());
+        // Nothing is left to release.
+        assert!(engine
+            .apply(now(Transaction::ReviewRelease {
+                client: 1,
+                tx: 3,
+                amount: None,
+            }))
+            .is_err());
+        // A second dispute the same day is rejected.
+        engine.apply(now(Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        }))?;
+        let e = engine
+            .apply(now(Transaction::Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            }))
+            .unwrap_err();
+        assert!(e.downcast_ref::<RiskRejected>().is_some());
+        assert_eq!(engine.take_alerts()[0].action, Action::Reject);
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().held, 100.into());
+        engine.verify()
+    }
+
+    // A risk rule rejecting anything but a deposit.
+    #[derive(Debug)]
+    struct DepositsOnly;
+
+    impl RiskRule for DepositsOnly {
+        fn name(&self) -> &'static str {
+            "deposits_only"
+        }
+        fn window(&self) -> u64 {
+            0
+        }
+        fn score(&self, context: &Context) -> u32 {
+            match context.transaction {
+                Transaction::Deposit { .. } => 0,
+                _ => 100,
+            }
+        }
+    }
+
+    #[test]
+    fn transactions_the_engine_makes_itself_are_not_screened() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            risk: Rc::new(RiskPolicy::default().with_rule(Box::new(DepositsOnly))),
+            interest: Some(
+                InterestPolicy::new(vec![RateBand {
+                    tier: None,
+                    above: Decimal::ZERO,
+                    rate: Decimal::new(365, 1),
+                }])
+                .with_posting_days(1),
+            ),
+            reserves: HashMap::from([(
+                1,
+                Reserve {
+                    percentage: 10.into(),
+                    days: 1,
+                },
+            )]),
+            ..Default::default()
+        });
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(1000.into()),
+            },
+            timestamp: Some(0),
+        })?;
+        // The rule does apply to what is given to the engine.
+        assert!(engine
+            .apply(now(Transaction::ReserveRelease {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }))
+            .is_err());
+        assert_eq!(engine.take_alerts().len(), 1);
+        // A day on, 0.1% of the 900 available is credited and the reserve released.
+        engine.apply(TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 2,
+                tx: 2,
+                amount: Some(1.into()),
+            },
+            timestamp: Some(DAY),
+        })?;
+        assert!(engine.take_errors().is_empty());
+        assert!(engine.take_alerts().is_empty());
+        let account = engine.account(1).expect("account 1 to exist");
+        assert_eq!(account.balances().available, Decimal::new(10009, 1));
+        assert_eq!(account.balances().held, Decimal::ZERO);
+        engine.verify()
+    }
+
+    #[test]
+    fn clients_turning_critical_are_reported_and_may_be_locked() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            chargeback_ratio: Some(RatioPolicy::new(20.into(), 50.into()).with_lock(true)),
+            ..Default::default()
+        });
+        for tx in 1..=4 {
+            engine.apply(now(Transaction::Deposit {
+                client: 1,
+                tx,
+                amount: Some(10.into()),
+            }))?;
+        }
+        let dispute = |tx| {
+            now(Transaction::Dispute {
+                client: 1,
+                tx,
+                amount: None,
+            })
+        };
+        engine.apply(dispute(1))?;
+        let report = engine.ratio_report();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].level, Level::Warning);
+        assert!(!report[0].locked);
+        engine.apply(dispute(2))?;
+        let report = engine.ratio_report();
+        assert_eq!(report[0].level, Level::Critical);
+        assert!(report[0].locked);
+        engine.verify()
+    }
+
+    #[test]
+    fn transactions_of_blocked_clients_are_rejected_or_held() -> Result<()> {
+        let listing = |client, reason: &str, action| Listing {
+            client,
+            name: None,
+            reason: Some(reason.to_string()),
+            action,
+        };
+        let mut engine = Engine::default().with_blocklist(Blocklist::new(vec![
+            listing(1, "sanctions", blocklist::Action::Reject),
+            listing(2, "review", blocklist::Action::Hold),
+        ]));
+        let deposit = |client| {
+            now(Transaction::Deposit {
+                client,
+                tx: client.into(),
+                amount: Some(10.into()),
+            })
+        };
+        let e = engine.apply(deposit(1)).unwrap_err();
+        assert!(e.downcast_ref::<Blocked>().is_some());
+        engine.apply(deposit(3))?;
+        // Held clients are blocked too, until each transaction is released.
+        let release = |tx| {
+            now(Transaction::ReviewRelease {
+                client: 2,
+                tx,
+                amount: None,
+            })
+        };
+        let e = engine.apply(deposit(2)).unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<Blocked>().map(ToString::to_string),
+            Some("client 2 is blocked: review, held for review".to_string())
+        );
+        assert!(engine
+            .account(2)
+            .is_some_and(|account| account.balances().total.is_zero()));
+        engine.apply(release(2))?;
+        engine
+            .apply(now(Transaction::Withdrawal {
+                client: 2,
+                tx: 4,
+                amount: Some(5.into()),
+            }))
+            .unwrap_err();
+        let alerts = engine.take_alerts();
+        assert_eq!(alerts.len(), 3);
+        assert_eq!(alerts[1].rules, "blocklist");
+        let account = engine.account(2).expect("account 2 to exist");
+        assert_eq!(account.balances().available, 10.into());
+        engine.apply(release(4))?;
+        let account = engine.account(2).expect("account 2 to exist");
+        assert_eq!(account.balances().available, 5.into());
+        let account = engine.account(3).expect("account 3 to exist");
+        assert_eq!(account.balances().available, 10.into());
+        engine.verify()
+    }
+
+    #[test]
+    fn dispute_cases_go_through_their_stages_with_notes_and_deadlines() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            case_deadlines: Deadlines(HashMap::from([(Stage::Chargeback, 30 * DAY)])),
+            ..Default::default()
+        });
+        let at = |timestamp, transaction| TimedTransaction {
+            transaction,
+            timestamp: Some(timestamp),
+        };
+        let (client, tx, amount) = (1, 1, None);
+        engine.apply(at(
+            0,
+            Transaction::Deposit {
+                client,
+                tx,
+                amount: Some(100.into()),
+            },
+        ))?;
+        engine.apply(at(0, Transaction::Inquiry { client, tx, amount }))?;
+        // An inquiry holds nothing and can't skip ahead to representment.
+        let balances = engine.account(1).expect("account 1 to exist").balances();
+        assert_eq!(balances.held, Decimal::ZERO);
+        assert!(engine
+            .apply(at(0, Transaction::Representment { client, tx, amount }))
+            .is_err());
+        engine.apply(at(DAY, Transaction::Dispute { client, tx, amount }))?;
+        engine.note(1, 1, "customer says the card was stolen".to_string())?;
+        engine.apply(at(2 * DAY, Transaction::Chargeback { client, tx, amount }))?;
+        let report = engine.case_report();
+        assert_eq!(report[0].stage, Stage::Chargeback);
+        assert_eq!(report[0].deadline, Some(32 * DAY));
+        assert_eq!(report[0].notes, 1);
+        // Past its deadline as soon as anything moves the clock on.
+        engine.apply(at(
+            40 * DAY,
+            Transaction::Deposit {
+                client: 2,
+                tx: 2,
+                amount: Some(1.into()),
+            },
+        ))?;
+        assert!(engine.case_report()[0].overdue);
+        engine.apply(at(
+            40 * DAY,
+            Transaction::Representment { client, tx, amount },
+        ))?;
+        // Representment holds what was charged back again, pre-arbitration takes it back.
+        let balances = engine.account(1).expect("account 1 to exist").balances();
+        assert_eq!(balances.held, 100.into());
+        assert_eq!(
+            engine.ledger().balance(LedgerAccount::ChargebackLoss),
+            0.into()
+        );
+        engine.apply(at(
+            41 * DAY,
+            Transaction::PreArbitration { client, tx, amount },
+        ))?;
+        assert_eq!(
+            engine.ledger().balance(LedgerAccount::ChargebackLoss),
+            100.into()
+        );
+        // Arbitration awards no more than was charged back.
+        let award = |amount: i64| {
+            at(
+                42 * DAY,
+                Transaction::Arbitration {
+                    client,
+                    tx,
+                    amount: Some(amount.into()),
+                },
+            )
+        };
+        assert!(engine.apply(award(101)).is_err());
+        engine.apply(award(60))?;
+        let balances = engine.account(1).expect("account 1 to exist").balances();
+        assert_eq!((balances.available, balances.held), (60.into(), 0.into()));
+        let report = engine.case_report();
+        assert_eq!(report[0].stage, Stage::Arbitration);
+        assert_eq!(report[0].deadline, None);
+        assert!(engine.apply(award(0)).is_err());
+        engine.verify()
+    }
+
+    #[test]
+    fn a_case_is_for_what_is_left_of_the_deposit_once_disputed() -> Result<()> {
+        let mut engine = Engine::default();
+        let (client, tx) = (1, 1);
+        for transaction in [
+            Transaction::Deposit {
+                client,
+                tx,
+                amount: Some(100.into()),
+            },
+            Transaction::Inquiry {
+                client,
+                tx,
+                amount: None,
+            },
+            Transaction::Reversal {
+                client,
+                tx,
+                amount: Some(60.into()),
+            },
+            Transaction::Dispute {
+                client,
+                tx,
+                amount: None,
+            },
+            Transaction::Chargeback {
+                client,
+                tx,
+                amount: None,
+            },
+            Transaction::Representment {
+                client,
+                tx,
+                amount: None,
+            },
+        ] {
+            engine.apply(now(transaction))?;
+        }
+        assert_eq!(engine.case_report()[0].amount, 40.into());
+        assert_eq!(
+            engine.ledger().balance(LedgerAccount::ChargebackLoss),
+            0.into()
+        );
+        engine.apply(now(Transaction::Resolve {
+            client,
+            tx,
+            amount: None,
+        }))?;
+        let balances = engine.account(1).expect("account 1 to exist").balances();
+        assert_eq!((balances.available, balances.held), (40.into(), 0.into()));
+        engine.verify()
+    }
+}
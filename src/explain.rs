@@ -0,0 +1,262 @@
+use rust_decimal::Decimal;
+use std::{collections::BTreeMap, fmt};
+
+use crate::account::Account;
+use crate::history::{HistoryEntry, Outcome};
+use crate::hold::HoldReason;
+
+// A step by step derivation of how a client's balances came to be, worked out
+// from the account's history, along with what the account holds right now.
+#[derive(Debug, Default)]
+pub struct Explanation<'a> {
+    client: u16,
+    steps: Vec<&'a HistoryEntry>,
+    // What each kind of transaction added to available and held.
+    available: BTreeMap<&'static str, Decimal>,
+    held: BTreeMap<&'static str, Decimal>,
+    // What is held right now and why, by (reason, tx id).
+    holds: BTreeMap<(HoldReason, u32), Decimal>,
+    // The step that locked the account, if it is locked.
+    locked_by: Option<&'a HistoryEntry>,
+}
+
+impl<'a> Explanation<'a> {
+    pub fn new(account: &'a Account) -> Self {
+        let mut explanation = Explanation {
+            client: *account.get_client(),
+            holds: account
+                .holds()
+                .map(|hold| ((hold.reason, hold.id), hold.amount))
+                .collect(),
+            ..Default::default()
+        };
+        for entry in account.get_history() {
+            explanation.steps.push(entry);
+            if entry.outcome != Outcome::Applied {
+                continue;
+            }
+            let kind = entry.transaction.get_kind();
+            *explanation.available.entry(kind).or_default() +=
+                entry.after.available - entry.before.available;
+            *explanation.held.entry(kind).or_default() += entry.after.held - entry.before.held;
+            if entry.after.locked && !entry.before.locked {
+                explanation.locked_by = Some(entry);
+            }
+        }
+        explanation
+    }
+}
+
+// What a hold of the given reason is for, e.g "dispute of deposit" 2.
+fn describe(reason: HoldReason) -> &'static str {
+    match reason {
+        HoldReason::Dispute => "dispute of deposit",
+        HoldReason::Authorization => "authorization",
+        HoldReason::Reserve => "reserve of deposit",
+        HoldReason::Legal => "legal hold",
+    }
+}
+
+// Writes out a breakdown such as "10.0000 = deposit 15.0000 + withdrawal -5.0000".
+fn write_breakdown(
+    f: &mut fmt::Formatter,
+    name: &str,
+    parts: &BTreeMap<&'static str, Decimal>,
+) -> fmt::Result {
+    let sum: Decimal = parts.values().sum();
+    write!(f, "{} {:.4} =", name, sum)?;
+    let mut parts = parts
+        .iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .peekable();
+    if parts.peek().is_none() {
+        write!(f, " 0.0000")?;
+    }
+    for (i, (kind, amount)) in parts.enumerate() {
+        if i > 0 {
+            write!(f, " +")?;
+        }
+        write!(f, " {} {:.4}", kind, amount)?;
+    }
+    writeln!(f)
+}
+
+impl<'a> fmt::Display for Explanation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "client {}", self.client)?;
+        writeln!(f)?;
+        for entry in &self.steps {
+            write!(
+                f,
+                "#{} {} tx {}",
+                entry.seq,
+                entry.transaction.get_kind(),
+                entry.transaction.get_tx()
+            )?;
+            if let Some(amount) = entry.transaction.get_amount() {
+                write!(f, " of {:.4}", amount)?;
+            }
+            match &entry.outcome {
+                Outcome::Rejected(reason) => writeln!(f, ": rejected, {}", reason)?,
+                Outcome::Applied => {
+                    write!(
+                        f,
+                        ": available {:.4} -> {:.4}, held {:.4} -> {:.4}",
+                        entry.before.available,
+                        entry.after.available,
+                        entry.before.held,
+                        entry.after.held
+                    )?;
+                    if entry.after.locked && !entry.before.locked {
+                        write!(f, ", locked")?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+        writeln!(f)?;
+        write_breakdown(f, "available", &self.available)?;
+        write_breakdown(f, "held", &self.held)?;
+        if self.holds.is_empty() {
+            writeln!(f, "nothing is held")?;
+        }
+        for ((reason, tx), amount) in &self.holds {
+            writeln!(f, "held {:.4} for {} {}", amount, describe(*reason), tx)?;
+        }
+        match self.locked_by {
+            Some(entry) => writeln!(
+                f,
+                "locked by {} of tx {} at #{}",
+                entry.transaction.get_kind(),
+                entry.transaction.get_tx(),
+                entry.seq
+            ),
+            None => writeln!(f, "not locked"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Explanation;
+    use crate::engine::{Config, Engine};
+    use crate::history::HistoryScope;
+    use crate::hold::HoldReason;
+    use crate::reserve::Reserve;
+    use crate::transaction::TimedTransaction;
+    use crate::Transaction;
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    #[test]
+    fn an_explanation_names_what_is_held_and_what_locked_the_account() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            history: HistoryScope::Client(1),
+            ..Default::default()
+        });
+        let transactions = [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(10.into()),
+            },
+            Transaction::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Some(5.into()),
+            },
+            Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+            Transaction::Dispute {
+                client: 1,
+                tx: 2,
+                amount: None,
+            },
+            Transaction::Chargeback {
+                client: 1,
+                tx: 1,
+                amount: None,
+            },
+        ];
+        for transaction in transactions {
+            engine.apply(TimedTransaction {
+                transaction,
+                timestamp: None,
+            })?;
+        }
+        let account = engine.account(1).expect("account 1 to exist");
+        let explanation = Explanation::new(account);
+        assert_eq!(explanation.holds.len(), 1);
+        assert_eq!(
+            explanation.holds.get(&(HoldReason::Dispute, 2)),
+            Some(&Decimal::from(5))
+        );
+        assert_eq!(explanation.locked_by.map(|entry| entry.seq), Some(5));
+        let text = explanation.to_string();
+        assert!(text.contains("available 0.0000 = deposit 15.0000 + dispute -15.0000"));
+        assert!(text.contains("held 5.0000 = chargeback -10.0000 + dispute 15.0000"));
+        assert!(text.contains("held 5.0000 for dispute of deposit 2"));
+        assert!(text.contains("locked by chargeback of tx 1 at #5"));
+        Ok(())
+    }
+
+    #[test]
+    fn a_reversed_deposit_is_no_longer_explained_as_reserved() -> Result<()> {
+        let mut engine = Engine::new(Config {
+            history: HistoryScope::Client(1),
+            reserves: HashMap::from([(
+                1,
+                Reserve {
+                    percentage: 10.into(),
+                    days: 30,
+                },
+            )]),
+            ..Default::default()
+        });
+        let transactions = [
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                amount: Some(100.into()),
+            },
+            Transaction::Deposit {
+                client: 1,
+                tx: 2,
+                amount: Some(100.into()),
+            },
+            // Partly reversed, leaving 5 of the 10 reserved.
+            Transaction::Reversal {
+                client: 1,
+                tx: 1,
+                amount: Some(50.into()),
+            },
+            // Fully reversed, leaving nothing reserved.
+            Transaction::Reversal {
+                client: 1,
+                tx: 2,
+                amount: None,
+            },
+        ];
+        for transaction in transactions {
+            engine.apply(TimedTransaction {
+                transaction,
+                timestamp: Some(0),
+            })?;
+        }
+        let account = engine.account(1).expect("account 1 to exist");
+        let explanation = Explanation::new(account);
+        assert_eq!(explanation.holds.len(), 1);
+        assert_eq!(
+            explanation.holds.get(&(HoldReason::Reserve, 1)),
+            Some(&Decimal::from(5))
+        );
+        let text = explanation.to_string();
+        assert!(text.contains("held 5.0000 for reserve of deposit 1"));
+        assert!(!text.contains("reserve of deposit 2"));
+        Ok(())
+    }
+}
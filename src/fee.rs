@@ -0,0 +1,261 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+// The kinds of transaction fees can be charged on. There are no currency
+// conversions in paperstack, so no fees for them either.
+const CHARGEABLE: [&str; 4] = ["deposit", "withdrawal", "capture", "chargeback"];
+
+// A fee of flat + percentage% of the amount, kept within min and max, charged
+// on transactions of the given kind for amounts of at least `above`. Rules
+// without a tier apply to clients that have no rules for their own tier.
+//
+// Several rules for the same kind and tier make a tiered fee, the one with the
+// highest `above` that the amount reaches applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeRule {
+    pub kind: String,
+    pub tier: Option<String>,
+    pub above: Decimal,
+    pub flat: Decimal,
+    pub percentage: Decimal,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeRule {
+    fn fee(&self, amount: Decimal) -> Decimal {
+        let mut fee = self.flat + amount * self.percentage / Decimal::ONE_HUNDRED;
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        fee.round_dp(4)
+    }
+}
+
+// Every fee rule along with the tier of each client. Clients without a tier
+// only get the rules without one.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct FeeSchedule {
+    rules: Vec<FeeRule>,
+    tiers: HashMap<u16, String>,
+}
+
+impl FeeSchedule {
+    pub fn new(rules: Vec<FeeRule>) -> Self {
+        FeeSchedule {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_tiers(mut self, tiers: HashMap<u16, String>) -> Self {
+        self.tiers = tiers;
+        self
+    }
+
+    // The fee for a transaction of the given kind and amount for the client.
+    pub fn fee(&self, client: u16, kind: &str, amount: Decimal) -> Decimal {
+        let tier = self.tiers.get(&client);
+        let applies = |rule: &&FeeRule, tier: Option<&String>| {
+            rule.kind == kind && rule.tier.as_ref() == tier
+        };
+        // Rules for the client's own tier, if there are any, otherwise those for any tier.
+        let tier = tier.filter(|_| self.rules.iter().any(|rule| applies(&rule, tier)));
+        self.rules
+            .iter()
+            .filter(|rule| applies(rule, tier))
+            .filter(|rule| amount >= rule.above)
+            .max_by_key(|rule| rule.above)
+            .map_or(Decimal::ZERO, |rule| rule.fee(amount))
+    }
+}
+
+// The fee schedule file has a row per rule, e.g:
+//
+// type,       tier, above, flat, percentage, min, max
+// withdrawal,     ,     0,  0.5,          1,    ,  10
+// withdrawal,     ,  1000,    0,        0.5,    ,  10
+// withdrawal, gold,     0,    0,          0,    ,
+// chargeback,     ,     0,   15,           ,    ,
+#[derive(Deserialize, Debug)]
+struct FeeEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    tier: Option<String>,
+    above: Option<Decimal>,
+    flat: Option<Decimal>,
+    percentage: Option<Decimal>,
+    min: Option<Decimal>,
+    max: Option<Decimal>,
+}
+
+// Empty columns are no tier, a threshold of zero and no flat or percentage fee.
+impl TryFrom<FeeEntry> for FeeRule {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: FeeEntry) -> Result<Self> {
+        if !CHARGEABLE.contains(&entry.kind.as_str()) {
+            return Err(anyhow!("fees cannot be charged on {}", entry.kind));
+        }
+        let rule = FeeRule {
+            tier: entry.tier.filter(|tier| !tier.is_empty()),
+            above: entry.above.unwrap_or_default(),
+            flat: entry.flat.unwrap_or_default(),
+            percentage: entry.percentage.unwrap_or_default(),
+            min: entry.min,
+            max: entry.max,
+            kind: entry.kind,
+        };
+        let mut caps = [rule.min, rule.max].into_iter().flatten();
+        if rule.flat < Decimal::ZERO
+            || rule.percentage < Decimal::ZERO
+            || caps.any(|cap| cap < Decimal::ZERO)
+        {
+            return Err(anyhow!("fees for {} cannot be negative", rule.kind));
+        }
+        if let (Some(min), Some(max)) = (rule.min, rule.max) {
+            if min > max {
+                return Err(anyhow!(
+                    "fees for {} cannot have a min of {} above a max of {}",
+                    rule.kind,
+                    min,
+                    max
+                ));
+            }
+        }
+        Ok(rule)
+    }
+}
+
+pub fn load_fee_schedule<P: AsRef<Path>>(path: P) -> Result<FeeSchedule> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut rules = Vec::new();
+    for result in reader.deserialize::<FeeEntry>() {
+        rules.push(FeeRule::try_from(result?)?);
+    }
+    Ok(FeeSchedule::new(rules))
+}
+
+#[derive(Deserialize, Debug)]
+struct TierEntry {
+    client: u16,
+    tier: String,
+}
+
+// The client tiers file has a row per client with a tier, e.g:
+//
+// client, tier
+// 1,      gold
+pub fn load_tiers<P: AsRef<Path>>(path: P) -> Result<HashMap<u16, String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut tiers = HashMap::new();
+    for result in reader.deserialize::<TierEntry>() {
+        let entry = result?;
+        tiers.insert(entry.client, entry.tier);
+    }
+    Ok(tiers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_fee_schedule, FeeEntry, FeeRule, FeeSchedule};
+    use rust_decimal::Decimal;
+    use std::{collections::HashMap, env, fs};
+
+    fn rule(kind: &str, tier: Option<&str>, above: i64, flat: i64, percentage: i64) -> FeeRule {
+        FeeRule {
+            kind: kind.to_string(),
+            tier: tier.map(str::to_string),
+            above: above.into(),
+            flat: flat.into(),
+            percentage: percentage.into(),
+            min: None,
+            max: None,
+        }
+    }
+
+    #[test]
+    fn a_fee_is_flat_plus_a_percentage_within_its_caps() {
+        let mut rule = rule("withdrawal", None, 0, 1, 2);
+        assert_eq!(rule.fee(100.into()), 3.into());
+        rule.min = Some(5.into());
+        assert_eq!(rule.fee(100.into()), 5.into());
+        rule.max = Some(6.into());
+        assert_eq!(rule.fee(1000.into()), 6.into());
+    }
+
+    #[test]
+    fn the_highest_tier_reached_applies_and_client_tiers_come_first() {
+        let schedule = FeeSchedule {
+            rules: vec![
+                rule("withdrawal", None, 0, 1, 0),
+                rule("withdrawal", None, 100, 2, 0),
+                rule("withdrawal", Some("gold"), 0, 0, 0),
+                rule("chargeback", None, 0, 15, 0),
+            ],
+            ..Default::default()
+        }
+        .with_tiers(HashMap::from([
+            (1, "gold".to_string()),
+            (2, "silver".to_string()),
+        ]));
+        assert_eq!(schedule.fee(3, "withdrawal", 99.into()), 1.into());
+        assert_eq!(schedule.fee(3, "withdrawal", 100.into()), 2.into());
+        assert_eq!(schedule.fee(1, "withdrawal", 100.into()), Decimal::ZERO);
+        assert_eq!(schedule.fee(2, "withdrawal", 100.into()), 2.into());
+        assert_eq!(schedule.fee(1, "chargeback", 100.into()), 15.into());
+        assert_eq!(schedule.fee(1, "deposit", 100.into()), Decimal::ZERO);
+    }
+
+    #[test]
+    fn fee_rules_are_loaded_with_empty_columns_left_out() -> anyhow::Result<()> {
+        let path = env::temp_dir().join(format!("paperstack-fees-{}", std::process::id()));
+        fs::write(
+            &path,
+            "type,tier,above,flat,percentage,min,max\n\
+             withdrawal,,0,1,10,,\n\
+             deposit,,0,0.5,,,\n",
+        )?;
+        let loaded = load_fee_schedule(&path);
+        fs::write(
+            &path,
+            "type,tier,above,flat,percentage,min,max\nconversion,,0,1,,,\n",
+        )?;
+        let unchargeable = load_fee_schedule(&path);
+        fs::remove_file(&path)?;
+        let mut deposit = rule("deposit", None, 0, 0, 0);
+        deposit.flat = Decimal::new(5, 1);
+        assert_eq!(
+            loaded?,
+            FeeSchedule::new(vec![rule("withdrawal", None, 0, 1, 10), deposit])
+        );
+        assert!(unchargeable.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fee_caps_must_not_be_negative_nor_the_wrong_way_round() {
+        let entry = |min: i64, max: i64| FeeEntry {
+            kind: "withdrawal".to_string(),
+            tier: None,
+            above: None,
+            flat: None,
+            percentage: Some(1.into()),
+            min: Some(min.into()),
+            max: Some(max.into()),
+        };
+        assert!(FeeRule::try_from(entry(-1, 10)).is_err());
+        assert!(FeeRule::try_from(entry(0, -1)).is_err());
+        assert!(FeeRule::try_from(entry(10, 5)).is_err());
+        assert!(FeeRule::try_from(entry(5, 5)).is_ok());
+    }
+}
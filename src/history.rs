@@ -0,0 +1,124 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::account::{serialize_with_fixed_digits, Balances};
+use crate::clock::Timestamp;
+use crate::Transaction;
+
+// Which accounts keep a history of the transactions applied to them. Keeping
+// history costs memory for every transaction, so none do by default.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryScope {
+    #[default]
+    None,
+    Client(u16),
+    All,
+}
+
+impl HistoryScope {
+    pub fn includes(&self, client: u16) -> bool {
+        match self {
+            HistoryScope::None => false,
+            HistoryScope::Client(included) => *included == client,
+            HistoryScope::All => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Applied,
+    Rejected(String),
+}
+
+// Something that happened to an account, good or bad, along with its balances
+// before and after. `seq` is the (1-based) input row that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub seq: u64,
+    pub timestamp: Option<Timestamp>,
+    pub transaction: Transaction,
+    pub outcome: Outcome,
+    pub before: Balances,
+    pub after: Balances,
+}
+
+// A change to an account's balances, flattened into a csv row. Unlike history
+// these are cheap enough to produce for every client, as they are written out
+// as they happen rather than kept around.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BalanceChange {
+    pub client: u16,
+    pub seq: u64,
+    pub timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub tx: u32,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub available: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub held: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+// A history entry flattened into a csv row.
+#[derive(Serialize, Debug)]
+pub struct HistoryLine<'a> {
+    seq: u64,
+    timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    tx: u32,
+    amount: Option<Decimal>,
+    outcome: &'static str,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    available_before: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    held_before: Decimal,
+    locked_before: bool,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    available_after: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    held_after: Decimal,
+    locked_after: bool,
+    reason: &'a str,
+}
+
+impl<'a> From<&'a HistoryEntry> for HistoryLine<'a> {
+    fn from(entry: &'a HistoryEntry) -> Self {
+        let (outcome, reason) = match &entry.outcome {
+            Outcome::Applied => ("applied", ""),
+            Outcome::Rejected(reason) => ("rejected", reason.as_str()),
+        };
+        HistoryLine {
+            seq: entry.seq,
+            timestamp: entry.timestamp,
+            kind: entry.transaction.get_kind(),
+            tx: *entry.transaction.get_tx(),
+            amount: *entry.transaction.get_amount(),
+            outcome,
+            available_before: entry.before.available,
+            held_before: entry.before.held,
+            locked_before: entry.before.locked,
+            available_after: entry.after.available,
+            held_after: entry.after.held,
+            locked_after: entry.after.locked,
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HistoryScope;
+
+    #[test]
+    fn a_history_scope_includes_the_clients_it_covers() {
+        assert!(!HistoryScope::None.includes(1));
+        assert!(HistoryScope::Client(1).includes(1));
+        assert!(!HistoryScope::Client(1).includes(2));
+        assert!(HistoryScope::All.includes(2));
+    }
+}
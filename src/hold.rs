@@ -0,0 +1,39 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt;
+
+use crate::account::serialize_with_fixed_digits;
+
+// Why funds are held. Each hold also has an id, which is the tx id of what it
+// is for: the disputed deposit, the authorization, the reserved deposit or the
+// legal hold.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldReason {
+    Dispute,
+    Authorization,
+    Reserve,
+    Legal,
+}
+
+impl fmt::Display for HoldReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HoldReason::Dispute => "dispute",
+            HoldReason::Authorization => "authorization",
+            HoldReason::Reserve => "reserve",
+            HoldReason::Legal => "legal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A single hold flattened into a csv row.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct HoldLine {
+    pub client: u16,
+    pub reason: HoldReason,
+    pub id: u32,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub amount: Decimal,
+}
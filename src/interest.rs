@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+// Accrued interest is credited every this many days unless told otherwise.
+pub const DEFAULT_POSTING_DAYS: u64 = 30;
+
+const DAYS_PER_YEAR: u64 = 365;
+
+// An annual interest rate, in percent, earned on available balances of at
+// least `above`. Bands without a tier apply to clients that have no bands for
+// their own tier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateBand {
+    pub tier: Option<String>,
+    pub above: Decimal,
+    pub rate: Decimal,
+}
+
+// Which interest rates apply to whom, and how often accrued interest is
+// credited. A balance earns the rate of the highest band it reaches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterestPolicy {
+    bands: Vec<RateBand>,
+    tiers: HashMap<u16, String>,
+    pub posting_days: u64,
+}
+
+impl InterestPolicy {
+    pub fn new(bands: Vec<RateBand>) -> Self {
+        InterestPolicy {
+            bands,
+            tiers: HashMap::new(),
+            posting_days: DEFAULT_POSTING_DAYS,
+        }
+    }
+
+    pub fn with_tiers(mut self, tiers: HashMap<u16, String>) -> Self {
+        self.tiers = tiers;
+        self
+    }
+
+    pub fn with_posting_days(mut self, days: u64) -> Self {
+        self.posting_days = days;
+        self
+    }
+
+    // The daily interest rate, as a fraction, on the client's available balance.
+    pub fn daily_rate(&self, client: u16, available: Decimal) -> Decimal {
+        let tier = self.tiers.get(&client);
+        let applies = |band: &&RateBand, tier: Option<&String>| band.tier.as_ref() == tier;
+        // Bands for the client's own tier, if there are any, otherwise those for any tier.
+        let tier = tier.filter(|_| self.bands.iter().any(|band| applies(&band, tier)));
+        self.bands
+            .iter()
+            .filter(|band| applies(band, tier) && available >= band.above)
+            .max_by_key(|band| band.above)
+            .map_or(Decimal::ZERO, |band| {
+                band.rate / Decimal::ONE_HUNDRED / Decimal::from(DAYS_PER_YEAR)
+            })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RateEntry {
+    tier: Option<String>,
+    above: Option<Decimal>,
+    rate: Decimal,
+}
+
+// The interest rates file has a row per band, e.g:
+//
+// tier,    above, rate
+//     ,        0, 0.5
+//     ,    10000, 1.5
+// savings,     0, 2.5
+pub fn load_interest_rates<P: AsRef<Path>>(path: P) -> Result<InterestPolicy> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut bands = Vec::new();
+    for result in reader.deserialize::<RateEntry>() {
+        let entry = result?;
+        if entry.rate < Decimal::ZERO {
+            return Err(anyhow!("interest rates cannot be negative"));
+        }
+        bands.push(RateBand {
+            tier: entry.tier.filter(|tier| !tier.is_empty()),
+            above: entry.above.unwrap_or_default(),
+            rate: entry.rate,
+        });
+    }
+    Ok(InterestPolicy::new(bands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_interest_rates, InterestPolicy, RateBand};
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+    use std::{collections::HashMap, env, fs};
+
+    fn band(tier: Option<&str>, above: i64, rate: i64) -> RateBand {
+        RateBand {
+            tier: tier.map(str::to_string),
+            above: above.into(),
+            rate: rate.into(),
+        }
+    }
+
+    #[test]
+    fn a_balance_earns_the_rate_of_the_highest_band_it_reaches() {
+        let policy = InterestPolicy::new(vec![
+            band(None, 0, 365),
+            band(None, 100, 730),
+            band(Some("savings"), 0, 1095),
+        ])
+        .with_tiers(HashMap::from([(1, "savings".to_string())]));
+        assert_eq!(policy.daily_rate(2, 99.into()), Decimal::new(1, 2));
+        assert_eq!(policy.daily_rate(2, 100.into()), Decimal::new(2, 2));
+        assert_eq!(policy.daily_rate(1, 100.into()), Decimal::new(3, 2));
+        assert_eq!(policy.daily_rate(2, (-1).into()), Decimal::ZERO);
+    }
+
+    #[test]
+    fn rate_bands_are_loaded_with_empty_columns_left_out() -> Result<()> {
+        let path = env::temp_dir().join(format!("paperstack-rates-{}", std::process::id()));
+        fs::write(&path, "tier,above,rate\n,,0.5\nsavings,100,2.5\n")?;
+        let policy = load_interest_rates(&path);
+        fs::remove_file(&path)?;
+        assert_eq!(
+            policy?,
+            InterestPolicy::new(vec![
+                RateBand {
+                    tier: None,
+                    above: 0.into(),
+                    rate: Decimal::new(5, 1),
+                },
+                RateBand {
+                    tier: Some("savings".to_string()),
+                    above: 100.into(),
+                    rate: Decimal::new(25, 1),
+                },
+            ])
+        );
+        Ok(())
+    }
+}
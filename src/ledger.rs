@@ -0,0 +1,196 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::account::Account;
+use crate::clock::Timestamp;
+
+// The accounts money is moved between. Client balances are what we owe the
+// client, so money coming in from outside (a deposit) increases the client's
+// available balance and decreases external settlement by the same amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAccount {
+    ClientAvailable(u16),
+    ClientHeld(u16),
+    // Money moving in and out of the system, i.e deposits and withdrawals.
+    ExternalSettlement,
+    // Money taken back by card networks through chargebacks.
+    ChargebackLoss,
+    FeeRevenue,
+    // Interest credited to clients.
+    InterestExpense,
+}
+
+impl LedgerAccount {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LedgerAccount::ClientAvailable(_) => "client_available",
+            LedgerAccount::ClientHeld(_) => "client_held",
+            LedgerAccount::ExternalSettlement => "external_settlement",
+            LedgerAccount::ChargebackLoss => "chargeback_loss",
+            LedgerAccount::FeeRevenue => "fee_revenue",
+            LedgerAccount::InterestExpense => "interest_expense",
+        }
+    }
+    pub fn client(&self) -> Option<u16> {
+        match self {
+            LedgerAccount::ClientAvailable(client) | LedgerAccount::ClientHeld(client) => {
+                Some(*client)
+            }
+            _ => None,
+        }
+    }
+}
+
+// One leg of a transfer. All postings made for a transaction sum to zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub amount: Decimal,
+}
+
+// Moving amount from one ledger account to another is two postings.
+pub fn transfer(from: LedgerAccount, to: LedgerAccount, amount: Decimal) -> [Posting; 2] {
+    [
+        Posting {
+            account: from,
+            amount: -amount,
+        },
+        Posting {
+            account: to,
+            amount,
+        },
+    ]
+}
+
+// Only the balance of each ledger account is kept rather than every posting,
+// so that the ledger doesn't grow with the number of transactions. For a record
+// of where money came from and where it went, the engine can hand over every
+// posting as it is made, see JournalLine.
+#[derive(Default, Debug, Clone)]
+pub struct Ledger {
+    balances: BTreeMap<LedgerAccount, Decimal>,
+}
+
+// A posting as a line in the journal, along with the input row (seq) and
+// transaction it was made for.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalLine {
+    pub seq: u64,
+    pub timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub tx: u32,
+    pub account: &'static str,
+    pub client: Option<u16>,
+    #[serde(serialize_with = "crate::account::serialize_with_fixed_digits")]
+    pub amount: Decimal,
+}
+
+// A line in the trial balance report.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TrialBalanceLine {
+    account: &'static str,
+    client: Option<u16>,
+    #[serde(serialize_with = "crate::account::serialize_with_fixed_digits")]
+    balance: Decimal,
+}
+
+impl Ledger {
+    pub fn post(&mut self, postings: &[Posting]) -> Result<()> {
+        let sum: Decimal = postings.iter().map(|posting| posting.amount).sum();
+        if !sum.is_zero() {
+            return Err(anyhow!(
+                "unbalanced postings {:?} sum to {:.4}",
+                postings,
+                sum
+            ));
+        }
+        for posting in postings {
+            *self.balances.entry(posting.account).or_default() += posting.amount;
+        }
+        Ok(())
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Decimal {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn trial_balance(&self) -> Vec<TrialBalanceLine> {
+        self.balances
+            .iter()
+            .map(|(account, balance)| TrialBalanceLine {
+                account: account.name(),
+                client: account.client(),
+                balance: *balance,
+            })
+            .collect()
+    }
+
+    // Every posting sums to zero, so the whole ledger must too, and the client
+    // ledger accounts must agree with the account balances.
+    pub fn verify<'a>(&self, accounts: impl Iterator<Item = &'a Account>) -> Result<()> {
+        let sum: Decimal = self.balances.values().sum();
+        if !sum.is_zero() {
+            return Err(anyhow!("ledger out of balance by {:.4}", sum));
+        }
+        for account in accounts {
+            let client = *account.get_client();
+            let available = self.balance(LedgerAccount::ClientAvailable(client));
+            let held = self.balance(LedgerAccount::ClientHeld(client));
+            if available != account.get_available() || held != account.get_held() {
+                return Err(anyhow!(
+                    "ledger has client {} at {:.4} available and {:.4} held but the account has {:.4} and {:.4}",
+                    client,
+                    available,
+                    held,
+                    account.get_available(),
+                    account.get_held()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{transfer, Ledger, LedgerAccount, Posting};
+    use anyhow::Result;
+
+    #[test]
+    fn unbalanced_postings_are_rejected() {
+        let mut ledger = Ledger::default();
+        assert!(ledger
+            .post(&[Posting {
+                account: LedgerAccount::ClientAvailable(1),
+                amount: 10.into(),
+            }])
+            .is_err());
+        assert_eq!(ledger.balance(LedgerAccount::ClientAvailable(1)), 0.into());
+    }
+
+    #[test]
+    fn transfers_move_money_between_ledger_accounts() -> Result<()> {
+        let mut ledger = Ledger::default();
+        ledger.post(&transfer(
+            LedgerAccount::ExternalSettlement,
+            LedgerAccount::ClientAvailable(1),
+            10.into(),
+        ))?;
+        ledger.post(&transfer(
+            LedgerAccount::ClientAvailable(1),
+            LedgerAccount::ClientHeld(1),
+            4.into(),
+        ))?;
+        assert_eq!(ledger.balance(LedgerAccount::ClientAvailable(1)), 6.into());
+        assert_eq!(ledger.balance(LedgerAccount::ClientHeld(1)), 4.into());
+        assert_eq!(
+            ledger.balance(LedgerAccount::ExternalSettlement),
+            (-10).into()
+        );
+        assert_eq!(ledger.trial_balance().len(), 3);
+        ledger.verify(std::iter::empty())
+    }
+}
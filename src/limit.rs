@@ -0,0 +1,337 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::VecDeque, error::Error, fmt, path::Path};
+
+use crate::clock::{has_elapsed, Timestamp, DAY};
+
+// Unless told otherwise, a warning is given once a withdrawal takes a client
+// to this percentage of a limit.
+const DEFAULT_WARN_AT: u32 = 80;
+
+// What a limit caps: how many withdrawals there are or how much is withdrawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measure {
+    Count,
+    Amount,
+}
+
+// A cap on withdrawals within a rolling window of some days. Limits without a
+// client apply to clients that have none of their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limit {
+    pub client: Option<u16>,
+    pub days: u64,
+    pub measure: Measure,
+    pub max: Decimal,
+    // The percentage of max at which a warning is given.
+    pub warn_at: Decimal,
+}
+
+// How much of a limit a client would use, were a withdrawal to go through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub client: u16,
+    pub days: u64,
+    pub measure: Measure,
+    pub max: Decimal,
+    pub used: Decimal,
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.measure {
+            Measure::Count => write!(
+                f,
+                "client {}: {} of at most {} withdrawals within {} days",
+                self.client, self.used, self.max, self.days
+            ),
+            Measure::Amount => write!(
+                f,
+                "client {}: {:.4} of at most {:.4} withdrawn within {} days",
+                self.client, self.used, self.max, self.days
+            ),
+        }
+    }
+}
+
+// The error a withdrawal is rejected with when it would exceed a limit, so
+// that it can be told apart from other rejections by downcasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded(pub Usage);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "withdrawal limit exceeded, {}", self.0)
+    }
+}
+
+impl Error for LimitExceeded {}
+
+// Every withdrawal limit.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    limits: Vec<Limit>,
+}
+
+impl Limits {
+    pub fn new(limits: Vec<Limit>) -> Self {
+        Limits { limits }
+    }
+
+    // Checks a withdrawal of amount at `now` against the limits that apply to
+    // the client, given its recent withdrawals. Returns the limits it would take
+    // the client near to, or the first one it would exceed.
+    pub fn check(
+        &self,
+        client: u16,
+        recent: &Withdrawals,
+        now: Option<Timestamp>,
+        amount: Decimal,
+    ) -> Result<Vec<Usage>, LimitExceeded> {
+        let own = self.limits.iter().any(|limit| limit.client == Some(client));
+        let mut warnings = Vec::new();
+        for limit in self
+            .limits
+            .iter()
+            .filter(|limit| limit.client == own.then_some(client))
+        {
+            let (count, sum) = recent.within(now, limit.days * DAY);
+            let used = match limit.measure {
+                Measure::Count => Decimal::from(count + 1),
+                Measure::Amount => sum + amount,
+            };
+            let usage = Usage {
+                client,
+                days: limit.days,
+                measure: limit.measure,
+                max: limit.max,
+                used,
+            };
+            if used > limit.max {
+                return Err(LimitExceeded(usage));
+            }
+            if used * Decimal::ONE_HUNDRED >= limit.max * limit.warn_at {
+                warnings.push(usage);
+            }
+        }
+        Ok(warnings)
+    }
+
+    // The longest window of any limit, beyond which withdrawals can be forgotten.
+    pub fn longest_window(&self) -> Option<u64> {
+        self.limits.iter().map(|limit| limit.days * DAY).max()
+    }
+}
+
+// The withdrawals of an account recent enough to count towards its limits,
+// oldest first, by when they happened and their tx id. Captures count as
+// withdrawals under the id of their authorization. Withdrawals at an unknown
+// time are never out of the window, so they are only counted and added up
+// rather than kept, or every row of an input without timestamps would be.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Withdrawals {
+    timed: VecDeque<(Timestamp, u32, Decimal)>,
+    untimed_count: usize,
+    untimed_amount: Decimal,
+}
+
+impl Withdrawals {
+    // Adds a withdrawal at `now`, forgetting those outside of the window.
+    pub fn push(&mut self, now: Option<Timestamp>, tx: u32, amount: Decimal, window: u64) {
+        while let Some((then, _, _)) = self.timed.front() {
+            if !has_elapsed(Some(*then), now, Some(window)) {
+                break;
+            }
+            self.timed.pop_front();
+        }
+        match now {
+            Some(now) => self.timed.push_back((now, tx, amount)),
+            None => {
+                self.untimed_count += 1;
+                self.untimed_amount += amount;
+            }
+        }
+    }
+
+    // Takes amount of the withdrawal tx made at `then` back, e.g as it was
+    // reversed or refunded, leaving `left` of it. Once nothing is left of it
+    // the withdrawal no longer counts at all.
+    pub fn take_back(&mut self, then: Option<Timestamp>, tx: u32, amount: Decimal, left: Decimal) {
+        if then.is_none() {
+            self.untimed_amount -= amount;
+            if left.is_zero() {
+                self.untimed_count = self.untimed_count.saturating_sub(1);
+            }
+            return;
+        }
+        if let Some(index) = self.timed.iter().position(|(_, id, _)| *id == tx) {
+            if left.is_zero() {
+                self.timed.remove(index);
+            } else {
+                self.timed[index].2 -= amount;
+            }
+        }
+    }
+
+    // How many withdrawals there were within the window before `now`, and how
+    // much they add up to.
+    fn within(&self, now: Option<Timestamp>, window: u64) -> (usize, Decimal) {
+        self.timed
+            .iter()
+            .filter(|(then, _, _)| !has_elapsed(Some(*then), now, Some(window)))
+            .fold(
+                (self.untimed_count, self.untimed_amount),
+                |(count, sum), (_, _, amount)| (count + 1, sum + amount),
+            )
+    }
+}
+
+// The withdrawal limits file has a row per limit, capping either the number
+// of withdrawals or the amount withdrawn within a window of days, e.g:
+//
+// client, days, count, amount, warn_at
+//       ,    1,     5,       ,
+//       ,    7,      ,  10000,      90
+// 42    ,    1,    20,       ,
+//
+// A row with both a count and an amount makes two limits. A warning is given
+// once a withdrawal takes a client to warn_at percent of a limit, 80 unless
+// given.
+#[derive(Deserialize, Debug)]
+struct LimitEntry {
+    client: Option<u16>,
+    days: u64,
+    count: Option<u32>,
+    amount: Option<Decimal>,
+    warn_at: Option<Decimal>,
+}
+
+pub fn load_limits<P: AsRef<Path>>(path: P) -> Result<Limits> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut limits = Vec::new();
+    for result in reader.deserialize::<LimitEntry>() {
+        let entry = result?;
+        if entry.days == 0 {
+            return Err(anyhow!("withdrawal limits need a window of at least a day"));
+        }
+        if entry.count.is_none() && entry.amount.is_none() {
+            return Err(anyhow!("withdrawal limits need a count, an amount or both"));
+        }
+        let warn_at = entry.warn_at.unwrap_or(DEFAULT_WARN_AT.into());
+        let measures = [
+            (Measure::Count, entry.count.map(Decimal::from)),
+            (Measure::Amount, entry.amount),
+        ];
+        for (measure, max) in measures {
+            if let Some(max) = max {
+                limits.push(Limit {
+                    client: entry.client,
+                    days: entry.days,
+                    measure,
+                    max,
+                    warn_at,
+                });
+            }
+        }
+    }
+    Ok(Limits::new(limits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_limits, Limit, LimitExceeded, Limits, Measure, Withdrawals};
+    use crate::clock::DAY;
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+    use std::{env, fs};
+
+    fn limit(client: Option<u16>, days: u64, measure: Measure, max: i64) -> Limit {
+        Limit {
+            client,
+            days,
+            measure,
+            max: max.into(),
+            warn_at: 80.into(),
+        }
+    }
+
+    #[test]
+    fn a_row_with_a_count_and_an_amount_makes_two_limits() -> Result<()> {
+        let path = env::temp_dir().join(format!("paperstack-limits-{}", std::process::id()));
+        fs::write(
+            &path,
+            "client,days,count,amount,warn_at\n,1,5,100,\n2,7,,50,90\n",
+        )?;
+        let limits = load_limits(&path);
+        fs::remove_file(&path)?;
+        let mut warned = limit(Some(2), 7, Measure::Amount, 50);
+        warned.warn_at = 90.into();
+        assert_eq!(
+            limits?,
+            Limits::new(vec![
+                limit(None, 1, Measure::Count, 5),
+                limit(None, 1, Measure::Amount, 100),
+                warned,
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawals_are_counted_and_summed_within_rolling_windows() {
+        let limits = Limits {
+            limits: vec![
+                limit(None, 1, Measure::Count, 3),
+                limit(None, 7, Measure::Amount, 100),
+                limit(Some(2), 1, Measure::Count, 1),
+            ],
+        };
+        let mut recent = Withdrawals::default();
+        let window = limits.longest_window().unwrap();
+        recent.push(Some(0), 1, 50.into(), window);
+        recent.push(Some(DAY / 2), 2, 10.into(), window);
+        // A third withdrawal the same day is near the count limit, a fourth exceeds it.
+        let warnings = limits.check(1, &recent, Some(DAY / 2), 1.into()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].measure, Measure::Count);
+        recent.push(Some(DAY / 2), 3, 1.into(), window);
+        let LimitExceeded(usage) = limits.check(1, &recent, Some(DAY), 1.into()).unwrap_err();
+        assert_eq!(usage.measure, Measure::Count);
+        // Two days on, only the sum over the week counts.
+        let LimitExceeded(usage) = limits
+            .check(1, &recent, Some(2 * DAY), 40.into())
+            .unwrap_err();
+        assert_eq!(usage.used, Decimal::from(101));
+        assert!(limits.check(1, &recent, Some(8 * DAY), 40.into()).is_ok());
+        // What is taken back no longer counts, and once all of it is, neither
+        // does the withdrawal.
+        recent.take_back(Some(0), 1, 20.into(), 30.into());
+        assert!(limits.check(1, &recent, Some(2 * DAY), 40.into()).is_ok());
+        recent.take_back(Some(0), 1, 30.into(), Decimal::ZERO);
+        assert!(limits.check(1, &recent, Some(DAY), 1.into()).is_ok());
+        // Clients with limits of their own don't get the others.
+        assert!(limits
+            .check(2, &Withdrawals::default(), Some(0), 1000.into())
+            .is_ok());
+    }
+
+    #[test]
+    fn withdrawals_at_an_unknown_time_are_added_up_rather_than_kept() {
+        let limits = Limits {
+            limits: vec![limit(None, 1, Measure::Amount, 1000)],
+        };
+        let mut recent = Withdrawals::default();
+        let window = limits.longest_window().unwrap();
+        for tx in 1..=1000 {
+            recent.push(None, tx, 1.into(), window);
+        }
+        assert!(recent.timed.is_empty());
+        assert!(limits.check(1, &recent, Some(100 * DAY), 1.into()).is_err());
+        recent.take_back(None, 1, 1.into(), Decimal::ZERO);
+        assert_eq!(recent.within(None, window), (999, 999.into()));
+        assert!(limits.check(1, &recent, None, 1.into()).is_ok());
+    }
+}
@@ -1,49 +1,395 @@
 mod account;
-use account::Account;
+
+mod aml;
+
+mod blocklist;
+
+mod case;
+
+mod cli;
+use cli::{Command, Format, Options};
+
+mod clock;
+
+mod diff;
+
+mod engine;
+use engine::{Engine, PastCutoff};
+
+mod fee;
+
+mod explain;
+use explain::Explanation;
+
+mod interest;
+
+mod limit;
+
+mod hold;
+
+mod history;
+use history::{HistoryLine, HistoryScope};
+
+mod ledger;
+
+mod overdraft;
+
+mod ratio;
+
+mod reconcile;
+
+mod snapshot;
+
+mod statement;
+use statement::{Html, Statement};
+
+mod reserve;
+
+mod risk;
+
+mod store;
+use store::TieredStore;
 
 mod transaction;
-use transaction::Transaction;
+use transaction::{NotedTransaction, TimedTransaction, Transaction};
 
 use csv::Trim;
-use std::{collections::HashMap, env, error::Error, ffi::OsString, io};
+use std::{env, error::Error, io, rc::Rc};
+
+// How often, in rows, to check whether the blocklist has been modified.
+const BLOCKLIST_RELOAD_ROWS: u64 = 10_000;
 
 fn main() -> anyhow::Result<(), Box<dyn Error>> {
-    // Assume the only argument is the path to a csv containing transactions, fail if no path is provided
-    let csv_path = match env::args_os().nth(1) {
-        None => Err::<OsString, Box<dyn Error>>(From::from("expected 1 argument, but got none")),
-        Some(file_path) => Ok(file_path),
-    }?;
+    // The only required argument is the path to a csv containing transactions, fail if no path is provided
+    let mut options = Options::parse(env::args_os().skip(1))?;
+    if let Some(path) = &options.overdraft_limits {
+        options.config.overdrafts = overdraft::load_overdrafts(path)?;
+    }
+    if let Some(path) = &options.withdrawal_limits {
+        options.config.limits = Rc::new(limit::load_limits(path)?);
+    }
+    if let Some(path) = &options.risk_rules {
+        let mut risk = risk::load_risk_rules(path)?;
+        if let Some([flag_at, hold_at, reject_at]) = options.risk_thresholds {
+            risk = risk.with_thresholds(flag_at, hold_at, reject_at);
+        }
+        options.config.risk = Rc::new(risk);
+    }
+    if let Some(path) = &options.case_deadlines {
+        options.config.case_deadlines = case::load_case_deadlines(path)?;
+    }
+    if let Some(path) = &options.reserves {
+        options.config.reserves = reserve::load_reserves(path)?;
+    }
+    if let Some(path) = &options.aml_rules {
+        options.config.aml_rules = Rc::new(aml::load_aml_rules(path)?);
+    }
+    let tiers = match &options.client_tiers {
+        Some(path) => fee::load_tiers(path)?,
+        None => Default::default(),
+    };
+    if let Some(path) = &options.fees {
+        let fees = fee::load_fee_schedule(path)?.with_tiers(tiers.clone());
+        options.config.fees = Rc::new(fees);
+    }
+    if let Some(path) = &options.interest_rates {
+        let mut interest = interest::load_interest_rates(path)?.with_tiers(tiers);
+        if let Some(days) = options.interest_posting_days {
+            interest = interest.with_posting_days(days);
+        }
+        options.config.interest = Some(interest);
+    }
+    options.config.balance_changes = options.timeseries.is_some();
+    options.config.journal = options.journal.is_some();
+    if let Command::History { client }
+    | Command::Explain { client }
+    | Command::Case { client, .. } = options.command
+    {
+        options.config.history = HistoryScope::Client(client);
+    }
+    if let Command::Statement { client, .. } = options.command {
+        options.config.history = client.map_or(HistoryScope::All, HistoryScope::Client);
+    }
+    // When asked for balances as of some point in the input, the engine stops there.
+    if let Command::Balances { as_of } = options.command {
+        options.config.as_of = Some(as_of);
+    }
 
     // Create a ReaderBuilder so that we may configure it to allow whitespace.
-    let mut reader = csv::ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_path(csv_path)?;
+    // Diffing is done on account files alone, there are no transactions to process.
+    let reader = match options.command {
+        Command::Diff { .. } => None,
+        _ => Some(
+            csv::ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_path(&options.csv_path)?,
+        ),
+    };
 
     // Read every transaction in the order they come in - this is the only ordering available to us as tx ids,
     // while unique u32:s, don't actually imply any ordering.
-    let mut accounts = HashMap::<u16, Account>::new();
-    for result in reader.deserialize::<Transaction>() {
-        let tx = result.expect("transaction to be deserialized");
-        // Here we're trying to either find an account with the correct client id or create a new one
-        // if one doesn't exist.
-        let account = accounts
-            .entry(*tx.get_client())
-            .or_insert_with(|| Account::new(*tx.get_client()));
-
+    let mut engine = Engine::new(options.config);
+    if let Some(path) = &options.deposit_store {
+        engine =
+            engine.with_deposit_store(Box::new(TieredStore::new(path, options.deposit_cache)?));
+    }
+    if let Some(path) = &options.blocklist {
+        engine = engine.with_blocklist(blocklist::load_blocklist(path)?);
+    }
+    let mut aml_report = match &options.aml_report {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    let mut timeseries = match &options.timeseries {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    let mut journal = match &options.journal {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    let mut alerts = match &options.alerts {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    // Whatever the engine has to tell besides how a transaction went is written
+    // out as it comes.
+    let mut drain = |engine: &mut Engine| -> Result<(), csv::Error> {
+        for e in engine.take_errors() {
+            eprintln!("{}", e);
+        }
+        for warning in engine.take_warnings() {
+            eprintln!("warning: near withdrawal limit, {}", warning);
+        }
+        if let Some(writer) = &mut aml_report {
+            for report in engine.take_reports() {
+                writer.serialize(report)?;
+            }
+        }
+        // Alerts go to their own stream, stderr unless told otherwise.
+        for alert in engine.take_alerts() {
+            match &mut alerts {
+                Some(writer) => writer.serialize(alert)?,
+                None => eprintln!("alert: {}", alert),
+            }
+        }
+        // Balance changes are written as we go so they don't pile up in memory.
+        if let Some(writer) = &mut timeseries {
+            for change in engine.take_changes() {
+                writer.serialize(change)?;
+            }
+        }
+        // And so are ledger postings.
+        if let Some(writer) = &mut journal {
+            for line in engine.take_journal() {
+                writer.serialize(line)?;
+            }
+        }
+        Ok(())
+    };
+    let transactions = reader
+        .into_iter()
+        .flat_map(|reader| reader.into_deserialize::<NotedTransaction>());
+    for (row, result) in (1..).zip(transactions) {
+        let NotedTransaction { timed: tx, note } = result.expect("transaction to be deserialized");
         // Then we apply the transaction that was deserialized to the account
         // in question.
         // If the transaction fails we print the error to stderr.
-        if let Err(e) = account.apply_transaction(tx) {
-            eprintln!("{}", e);
+        // There is no server mode, but a long run still picks up changes to the blocklist.
+        // A list caught half-written or briefly missing is no reason to stop, the
+        // previous one is kept until it can be read again.
+        if row % BLOCKLIST_RELOAD_ROWS == 0 {
+            match engine.reload_blocklist() {
+                Ok(true) => eprintln!("blocklist reloaded at row {}", row),
+                Ok(false) => (),
+                Err(e) => eprintln!("blocklist not reloaded at row {}, {}", row, e),
+            }
+        }
+        // Notes go with the case of transactions that went through.
+        let (client, id) = (*tx.transaction.get_client(), *tx.transaction.get_tx());
+        match engine.apply(tx) {
+            Err(e) if e.is::<PastCutoff>() => break,
+            Err(e) => eprintln!("{}", e),
+            Ok(()) => {
+                if let Some(note) = note {
+                    if let Err(e) = engine.note(client, id, note) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
         }
+        drain(&mut engine)?;
     }
-    // Finally we write our updated accounts to stdout.
+    // Whatever was due by a timestamp cutoff happens even if the input ends before it.
+    engine.finish();
+    drain(&mut engine)?;
+
     let mut csv_writer = csv::Writer::from_writer(io::stdout());
-    for (_, account) in accounts {
-        csv_writer
-            .serialize(account)
-            .expect("account to be serialized");
+    let mut breaks = 0;
+    match options.command {
+        // A dry run writes what the extra transactions would change instead.
+        Command::Accounts if options.dry_run.is_some() => {
+            let path = options.dry_run.as_ref().expect("a dry run to have a path");
+            let batch = csv::ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_path(path)?
+                .deserialize::<TimedTransaction>()
+                .collect::<Result<Vec<_>, _>>()?;
+            let simulation = engine.simulate(batch);
+            let changes = snapshot::changes(&simulation.before, &simulation.after);
+            for change in &changes {
+                csv_writer.serialize(diff::DiffRow::from(change))?;
+            }
+            for rejection in &simulation.rejections {
+                eprintln!(
+                    "row {}: {} tx {} would be rejected, {}",
+                    rejection.row,
+                    rejection.transaction.get_kind(),
+                    rejection.transaction.get_tx(),
+                    rejection.reason
+                );
+            }
+            for report in &simulation.reports {
+                eprintln!(
+                    "client {} would be reported for {}, tx {}",
+                    report.client, report.report, report.txs
+                );
+            }
+            eprint!("{}", diff::Summary::new(&changes));
+        }
+        // Finally we write our updated accounts to stdout.
+        Command::Accounts | Command::Balances { .. } => {
+            for account in engine.accounts() {
+                csv_writer
+                    .serialize(account)
+                    .expect("account to be serialized");
+            }
+        }
+        Command::History { client } => {
+            let account = engine
+                .account(client)
+                .ok_or_else(|| format!("no transactions for client {}", client))?;
+            for entry in account.get_history() {
+                csv_writer.serialize(HistoryLine::from(entry))?;
+            }
+        }
+        Command::Explain { client } => {
+            let account = engine
+                .account(client)
+                .ok_or_else(|| format!("no transactions for client {}", client))?;
+            print!("{}", Explanation::new(account));
+        }
+        Command::Case { client, tx } => {
+            let case = engine
+                .case(client, tx)
+                .ok_or_else(|| format!("client {} has no case {}", client, tx))?;
+            print!("{}", case);
+        }
+        Command::Statement {
+            client,
+            period,
+            format,
+        } => {
+            let mut accounts: Vec<_> = match client {
+                Some(client) => vec![engine
+                    .account(client)
+                    .ok_or_else(|| format!("no transactions for client {}", client))?],
+                None => engine.accounts().collect(),
+            };
+            accounts.sort_by_key(|account| *account.get_client());
+            let statements: Vec<_> = accounts
+                .into_iter()
+                .map(|account| Statement::new(*account.get_client(), account.get_history(), period))
+                .collect();
+            match format {
+                Format::Text => {
+                    for (i, statement) in statements.iter().enumerate() {
+                        if i > 0 {
+                            println!();
+                        }
+                        print!("{}", statement);
+                    }
+                }
+                Format::Csv => {
+                    for statement in &statements {
+                        for row in statement.rows() {
+                            csv_writer.serialize(row)?;
+                        }
+                    }
+                }
+                Format::Html => print!("{}", Html(&statements)),
+            }
+        }
+        Command::Reconcile { against, tolerance } => {
+            let external = snapshot::load_balances(against)?;
+            let computed = snapshot::balances_of(engine.accounts());
+            for difference in reconcile::reconcile(&computed, &external, tolerance) {
+                if difference.is_break {
+                    breaks += 1;
+                }
+                csv_writer.serialize(difference)?;
+            }
+        }
+        Command::Diff { before, after } => {
+            let changes = snapshot::changes(
+                &snapshot::load_balances(before)?,
+                &snapshot::load_balances(after)?,
+            );
+            for change in &changes {
+                csv_writer.serialize(diff::DiffRow::from(change))?;
+            }
+            eprint!("{}", diff::Summary::new(&changes));
+        }
     }
     csv_writer.flush()?;
+    if let Some(writer) = &mut timeseries {
+        writer.flush()?;
+    }
+    if let Some(writer) = &mut journal {
+        writer.flush()?;
+    }
+    if let Some(writer) = &mut alerts {
+        writer.flush()?;
+    }
+    if let Some(writer) = &mut aml_report {
+        writer.flush()?;
+    }
+
+    if let Some(path) = &options.chargeback_report {
+        let mut csv_writer = csv::Writer::from_path(path)?;
+        for line in engine.ratio_report() {
+            csv_writer.serialize(line)?;
+        }
+        csv_writer.flush()?;
+    }
+    if let Some(path) = &options.cases {
+        let mut csv_writer = csv::Writer::from_path(path)?;
+        for line in engine.case_report() {
+            csv_writer.serialize(line)?;
+        }
+        csv_writer.flush()?;
+    }
+    if let Some(path) = &options.trial_balance {
+        let mut csv_writer = csv::Writer::from_path(path)?;
+        for line in engine.ledger().trial_balance() {
+            csv_writer.serialize(line)?;
+        }
+        csv_writer.flush()?;
+    }
+    if let Some(path) = &options.holds {
+        let mut accounts: Vec<_> = engine.accounts().collect();
+        accounts.sort_by_key(|account| *account.get_client());
+        let mut csv_writer = csv::Writer::from_path(path)?;
+        for hold in accounts.iter().flat_map(|account| account.holds()) {
+            csv_writer.serialize(hold)?;
+        }
+        csv_writer.flush()?;
+    }
+    // The ledger should always balance and agree with the accounts, if it
+    // doesn't something is seriously wrong.
+    engine.verify()?;
+    if breaks > 0 {
+        return Err(format!("{} breaks above the tolerance", breaks).into());
+    }
     Ok(())
 }
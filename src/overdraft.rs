@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+// An approved credit line: withdrawals may take the available funds down
+// to -limit.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overdraft {
+    pub limit: Decimal,
+    pub mode: OverdraftMode,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverdraftMode {
+    #[default]
+    InterestFree,
+    // A fee of rate * the part of a withdrawal that dips into the credit line
+    // is charged on top of the withdrawal itself.
+    FeeBearing {
+        rate: Decimal,
+    },
+}
+
+impl Overdraft {
+    // The fee for taking the available funds from `before` to `after`. Only the
+    // part of the movement that is below zero counts.
+    pub fn fee(&self, before: Decimal, after: Decimal) -> Decimal {
+        match self.mode {
+            OverdraftMode::InterestFree => Decimal::ZERO,
+            OverdraftMode::FeeBearing { rate } => {
+                let used_before = (-before).max(Decimal::ZERO);
+                let used_after = (-after).max(Decimal::ZERO);
+                (used_after - used_before).max(Decimal::ZERO) * rate
+            }
+        }
+    }
+}
+
+// One line in the overdraft configuration file, e.g:
+//
+// client, limit, mode,         fee
+// 1,      500,   interest_free,
+// 2,      1000,  fee_bearing,  0.02
+#[derive(Deserialize, Debug)]
+struct OverdraftEntry {
+    client: u16,
+    limit: Decimal,
+    mode: OverdraftEntryMode,
+    fee: Option<Decimal>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum OverdraftEntryMode {
+    InterestFree,
+    FeeBearing,
+}
+
+impl TryFrom<OverdraftEntry> for Overdraft {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: OverdraftEntry) -> Result<Self> {
+        if entry.limit < Decimal::ZERO {
+            return Err(anyhow!(
+                "client {}: overdraft limit cannot be negative",
+                entry.client
+            ));
+        }
+        let mode = match entry.mode {
+            OverdraftEntryMode::InterestFree => OverdraftMode::InterestFree,
+            OverdraftEntryMode::FeeBearing => OverdraftMode::FeeBearing {
+                rate: entry.fee.ok_or_else(|| {
+                    anyhow!("client {}: fee bearing overdraft missing fee", entry.client)
+                })?,
+            },
+        };
+        // A negative fee would credit the client for using the credit line.
+        if let OverdraftMode::FeeBearing { rate } = mode {
+            if rate < Decimal::ZERO {
+                return Err(anyhow!(
+                    "client {}: overdraft fee cannot be negative",
+                    entry.client
+                ));
+            }
+        }
+        Ok(Overdraft {
+            limit: entry.limit,
+            mode,
+        })
+    }
+}
+
+pub fn load_overdrafts<P: AsRef<Path>>(path: P) -> Result<HashMap<u16, Overdraft>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut overdrafts = HashMap::new();
+    for result in reader.deserialize::<OverdraftEntry>() {
+        let entry = result?;
+        overdrafts.insert(entry.client, Overdraft::try_from(entry)?);
+    }
+    Ok(overdrafts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Overdraft, OverdraftEntry, OverdraftEntryMode, OverdraftMode};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn an_interest_free_overdraft_charges_no_fee() {
+        let overdraft = Overdraft {
+            limit: 100.into(),
+            mode: OverdraftMode::InterestFree,
+        };
+        assert_eq!(overdraft.fee(10.into(), (-50).into()), Decimal::ZERO);
+    }
+
+    #[test]
+    fn a_fee_bearing_overdraft_only_charges_for_the_overdrawn_part() {
+        let overdraft = Overdraft {
+            limit: 100.into(),
+            mode: OverdraftMode::FeeBearing {
+                rate: Decimal::new(1, 1),
+            },
+        };
+        assert_eq!(overdraft.fee(10.into(), (-50).into()), 5.into());
+        assert_eq!(overdraft.fee((-50).into(), (-60).into()), 1.into());
+        assert_eq!(overdraft.fee(60.into(), 50.into()), Decimal::ZERO);
+    }
+
+    #[test]
+    fn a_negative_overdraft_fee_is_rejected() {
+        let entry = |fee: i64| OverdraftEntry {
+            client: 1,
+            limit: 100.into(),
+            mode: OverdraftEntryMode::FeeBearing,
+            fee: Some(Decimal::new(fee, 2)),
+        };
+        assert!(Overdraft::try_from(entry(-2)).is_err());
+        assert_eq!(
+            Overdraft::try_from(entry(2)).ok(),
+            Some(Overdraft {
+                limit: 100.into(),
+                mode: OverdraftMode::FeeBearing {
+                    rate: Decimal::new(2, 2),
+                },
+            })
+        );
+    }
+}
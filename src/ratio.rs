@@ -0,0 +1,236 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::account::serialize_with_fixed_digits;
+use crate::clock::{has_elapsed, Timestamp, DAY};
+use crate::Transaction;
+
+// Card networks look at a month of activity unless told otherwise.
+pub const DEFAULT_RATIO_DAYS: u64 = 30;
+
+// How bad a client's chargeback ratio is.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Warning,
+    Critical,
+}
+
+// The chargeback ratio of a client is the number of disputes raised within a
+// rolling window, whether or not they end in a chargeback, as a percentage of
+// the deposits made within it. Clients at or above the warning or critical
+// percentage are reported, and those turning critical may be locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatioPolicy {
+    pub window: u64,
+    pub warning: Decimal,
+    pub critical: Decimal,
+    pub lock: bool,
+}
+
+impl RatioPolicy {
+    pub fn new(warning: Decimal, critical: Decimal) -> Self {
+        RatioPolicy {
+            window: DEFAULT_RATIO_DAYS * DAY,
+            warning,
+            critical,
+            lock: false,
+        }
+    }
+
+    pub fn with_window(mut self, window: u64) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_lock(mut self, lock: bool) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    pub fn level(&self, ratio: Decimal) -> Option<Level> {
+        if ratio >= self.critical {
+            Some(Level::Critical)
+        } else if ratio >= self.warning {
+            Some(Level::Warning)
+        } else {
+            None
+        }
+    }
+
+    // The client's line in the report as of `now`, if the client is over a threshold.
+    pub fn line(
+        &self,
+        client: u16,
+        tally: &Tally,
+        now: Option<Timestamp>,
+        locked: bool,
+    ) -> Option<RatioLine> {
+        let (deposits, disputes, chargebacks) = (
+            tally.deposits.within(now, self.window),
+            tally.disputes.within(now, self.window),
+            tally.chargebacks.within(now, self.window),
+        );
+        let ratio = ratio_of(disputes, deposits);
+        Some(RatioLine {
+            client,
+            deposits,
+            disputes,
+            chargebacks,
+            ratio,
+            level: self.level(ratio)?,
+            locked,
+        })
+    }
+}
+
+// Disputes as a percentage of deposits. Without any deposits every dispute
+// counts as a whole deposit's worth.
+fn ratio_of(disputes: usize, deposits: usize) -> Decimal {
+    (Decimal::from(disputes) * Decimal::ONE_HUNDRED / Decimal::from(deposits.max(1))).round_dp(4)
+}
+
+// The times of a client's recent deposits, disputes and chargebacks.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Tally {
+    deposits: Times,
+    disputes: Times,
+    chargebacks: Times,
+}
+
+// When something happened, oldest first. What happened at an unknown time is
+// never out of the window, so it is only counted rather than kept, or every
+// row of an input without timestamps would be.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+struct Times {
+    timed: VecDeque<Timestamp>,
+    untimed: usize,
+}
+
+impl Times {
+    fn push(&mut self, now: Option<Timestamp>) {
+        match now {
+            Some(now) => self.timed.push_back(now),
+            None => self.untimed += 1,
+        }
+    }
+
+    // Forgets whatever is outside of the window before `now`.
+    fn forget(&mut self, now: Option<Timestamp>, window: u64) {
+        while let Some(then) = self.timed.front() {
+            if !has_elapsed(Some(*then), now, Some(window)) {
+                break;
+            }
+            self.timed.pop_front();
+        }
+    }
+
+    // How many times something happened within the window before `now`.
+    fn within(&self, now: Option<Timestamp>, window: u64) -> usize {
+        self.untimed
+            + self
+                .timed
+                .iter()
+                .filter(|then| !has_elapsed(Some(**then), now, Some(window)))
+                .count()
+    }
+}
+
+impl Tally {
+    // Counts an applied transaction at `now`, forgetting whatever is outside of
+    // the window.
+    pub fn observe(&mut self, transaction: &Transaction, now: Option<Timestamp>, window: u64) {
+        for times in [
+            &mut self.deposits,
+            &mut self.disputes,
+            &mut self.chargebacks,
+        ] {
+            times.forget(now, window);
+        }
+        match transaction {
+            Transaction::Deposit { .. } => self.deposits.push(now),
+            Transaction::Dispute { .. } => self.disputes.push(now),
+            Transaction::Chargeback { .. } => self.chargebacks.push(now),
+            _ => (),
+        }
+    }
+}
+
+// A client over a threshold, as a line in the chargeback ratio report.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RatioLine {
+    pub client: u16,
+    pub deposits: usize,
+    pub disputes: usize,
+    pub chargebacks: usize,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub ratio: Decimal,
+    pub level: Level,
+    pub locked: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Level, RatioPolicy, Tally};
+    use crate::clock::DAY;
+    use crate::Transaction;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn disputes_are_counted_against_deposits_within_the_window() {
+        let policy = RatioPolicy::new(1.into(), 2.into()).with_window(10 * DAY);
+        let mut tally = Tally::default();
+        for tx in 1..=100 {
+            let deposit = Transaction::Deposit {
+                client: 1,
+                tx,
+                amount: Some(1.into()),
+            };
+            tally.observe(&deposit, Some(0), policy.window);
+        }
+        assert_eq!(policy.line(1, &tally, Some(0), false), None);
+        let dispute = Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        tally.observe(&dispute, Some(DAY), policy.window);
+        let line = policy.line(1, &tally, Some(DAY), false).unwrap();
+        assert_eq!(line.ratio, Decimal::ONE);
+        assert_eq!(line.level, Level::Warning);
+        tally.observe(&dispute, Some(DAY), policy.window);
+        let line = policy.line(1, &tally, Some(DAY), false).unwrap();
+        assert_eq!(line.level, Level::Critical);
+        // Once the deposits are out of the window, the disputes alone count.
+        let line = policy.line(1, &tally, Some(10 * DAY + 1), false).unwrap();
+        assert_eq!(line.deposits, 0);
+        assert_eq!(line.ratio, Decimal::from(200));
+    }
+
+    #[test]
+    fn without_timestamps_everything_is_counted_but_nothing_kept() {
+        let policy = RatioPolicy::new(1.into(), 2.into());
+        let mut tally = Tally::default();
+        for tx in 1..=1000 {
+            let deposit = Transaction::Deposit {
+                client: 1,
+                tx,
+                amount: Some(1.into()),
+            };
+            tally.observe(&deposit, None, policy.window);
+        }
+        let dispute = Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        for _ in 0..20 {
+            tally.observe(&dispute, None, policy.window);
+        }
+        assert!(tally.deposits.timed.is_empty());
+        let line = policy.line(1, &tally, None, false).unwrap();
+        assert_eq!(line.deposits, 1000);
+        assert_eq!(line.level, Level::Critical);
+    }
+}
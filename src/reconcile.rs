@@ -0,0 +1,117 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::account::{serialize_with_fixed_digits, Balances};
+use crate::snapshot;
+
+// What kind of difference there is between a computed and an external account.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Issue {
+    // The balances differ.
+    Mismatch,
+    // The external file has a client we have no account for.
+    Missing,
+    // We have an account for a client the external file doesn't have.
+    Extra,
+}
+
+// A client whose computed balances don't agree with the external ones. The
+// differences are computed less external, a side without the client counting
+// as all zero. Any difference above the tolerance, any difference in locked
+// state and any missing or extra client is a break.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub client: u16,
+    pub issue: Issue,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub available: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub held: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    pub total: Decimal,
+    pub locked_computed: Option<bool>,
+    pub locked_external: Option<bool>,
+    #[serde(rename = "break")]
+    pub is_break: bool,
+}
+
+// Every difference between computed and external balances, by client.
+pub fn reconcile(
+    computed: &BTreeMap<u16, Balances>,
+    external: &BTreeMap<u16, Balances>,
+    tolerance: Decimal,
+) -> Vec<Difference> {
+    snapshot::changes(external, computed)
+        .into_iter()
+        .map(|change| {
+            let issue = match (change.after, change.before) {
+                (Some(_), Some(_)) => Issue::Mismatch,
+                (None, _) => Issue::Missing,
+                (_, None) => Issue::Extra,
+            };
+            Difference {
+                client: change.client,
+                issue,
+                available: change.available,
+                held: change.held,
+                total: change.total,
+                locked_computed: change.after.map(|balances| balances.locked),
+                locked_external: change.before.map(|balances| balances.locked),
+                is_break: issue != Issue::Mismatch
+                    || change.locked_changed()
+                    || change.largest_amount() > tolerance,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconcile, Issue};
+    use crate::snapshot::{balances_after, deposit};
+    use crate::Transaction;
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn only_differences_are_reported_and_only_those_above_the_tolerance_break() -> Result<()> {
+        let computed = balances_after(vec![
+            deposit(1, 10.into()),
+            deposit(2, Decimal::new(10001, 3)),
+            deposit(3, 10.into()),
+            Transaction::Lock {
+                client: 3,
+                tx: 3,
+                amount: None,
+            },
+            deposit(4, 10.into()),
+        ])?;
+        let external = balances_after(vec![
+            deposit(1, 10.into()),
+            deposit(2, 10.into()),
+            deposit(3, 10.into()),
+            deposit(5, 10.into()),
+        ])?;
+        let differences = reconcile(&computed, &external, Decimal::new(1, 2));
+        let summary: Vec<_> = differences
+            .iter()
+            .map(|difference| (difference.client, difference.issue, difference.is_break))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (2, Issue::Mismatch, false),
+                (3, Issue::Mismatch, true),
+                (4, Issue::Extra, true),
+                (5, Issue::Missing, true),
+            ]
+        );
+        assert_eq!(differences[0].available, Decimal::new(1, 3));
+        assert_eq!(differences[3].total, (-10).into());
+        assert_eq!(differences[3].locked_computed, None);
+        assert!(reconcile(&computed, &external, Decimal::ZERO)[0].is_break);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+use crate::clock::{Timestamp, DAY};
+
+// A rolling reserve: a percentage of every deposit is held for a number of
+// days before it becomes available.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reserve {
+    pub percentage: Decimal,
+    pub days: u64,
+}
+
+impl Reserve {
+    // How much of a deposit of amount to hold.
+    pub fn portion_of(&self, amount: Decimal) -> Decimal {
+        (amount * self.percentage / Decimal::ONE_HUNDRED).round_dp(4)
+    }
+
+    // When something reserved at `now` is released. If we don't know what time
+    // it is, we can't know when that is either and it is held until released by hand.
+    pub fn release_at(&self, now: Option<Timestamp>) -> Result<Option<Timestamp>> {
+        now.map(|now| {
+            self.period()
+                .and_then(|period| now.checked_add(period))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "a reserve of {} days from {} is out of range",
+                        self.days,
+                        now
+                    )
+                })
+        })
+        .transpose()
+    }
+
+    // How long a reserve is held for, in seconds, unless that is too long to tell.
+    fn period(&self) -> Option<u64> {
+        self.days.checked_mul(DAY)
+    }
+}
+
+// One line in the reserves file, e.g:
+//
+// client, percentage, days
+// 1,      10,         90
+#[derive(Deserialize, Debug)]
+struct ReserveEntry {
+    client: u16,
+    percentage: Decimal,
+    days: u64,
+}
+
+pub fn load_reserves<P: AsRef<Path>>(path: P) -> Result<HashMap<u16, Reserve>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut reserves = HashMap::new();
+    for result in reader.deserialize::<ReserveEntry>() {
+        let entry = result?;
+        if entry.percentage < Decimal::ZERO || entry.percentage > Decimal::ONE_HUNDRED {
+            return Err(anyhow!(
+                "client {}: reserve percentage must be between 0 and 100",
+                entry.client
+            ));
+        }
+        let reserve = Reserve {
+            percentage: entry.percentage,
+            days: entry.days,
+        };
+        if reserve.period().is_none() {
+            return Err(anyhow!(
+                "client {}: a reserve of {} days is too long",
+                entry.client,
+                entry.days
+            ));
+        }
+        reserves.insert(entry.client, reserve);
+    }
+    Ok(reserves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reserve;
+    use crate::clock::DAY;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn a_reserve_holds_a_percentage_for_a_number_of_days() {
+        let reserve = Reserve {
+            percentage: 10.into(),
+            days: 90,
+        };
+        assert_eq!(
+            reserve.portion_of(Decimal::new(12345, 2)),
+            Decimal::new(12345, 3)
+        );
+        assert_eq!(reserve.release_at(Some(DAY)).ok(), Some(Some(91 * DAY)));
+        assert_eq!(reserve.release_at(None).ok(), Some(None));
+    }
+
+    #[test]
+    fn a_release_date_out_of_range_is_an_error() {
+        let reserve = Reserve {
+            percentage: 10.into(),
+            days: u64::MAX / DAY,
+        };
+        assert!(reserve.release_at(Some(0)).is_ok());
+        assert!(reserve.release_at(Some(DAY)).is_err());
+        let reserve = Reserve {
+            percentage: 10.into(),
+            days: u64::MAX,
+        };
+        assert!(reserve.period().is_none());
+        assert!(reserve.release_at(Some(0)).is_err());
+    }
+}
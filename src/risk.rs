@@ -0,0 +1,468 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, error::Error, fmt, path::Path};
+
+use crate::account::Balances;
+use crate::clock::{has_elapsed, Timestamp, DAY};
+use crate::Transaction;
+
+// Unless told otherwise, transactions scoring this much are flagged, held
+// for review or rejected.
+const FLAG_AT: u32 = 50;
+const HOLD_AT: u32 = 75;
+const REJECT_AT: u32 = 100;
+
+// What is done about a risky transaction. Every one of them raises an alert,
+// held ones are set aside until a review_release with their tx id lets them
+// through and rejected ones aren't applied at all.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Flag,
+    Hold,
+    Reject,
+}
+
+// What a rule gets to see of a transaction: the transaction itself, when it
+// happens and the state of the account before it.
+pub struct Context<'a> {
+    pub transaction: &'a Transaction,
+    pub now: Option<Timestamp>,
+    pub balances: Balances,
+    pub activity: &'a Activity,
+}
+
+// A rule scoring how risky a transaction looks. The scores of every rule are
+// added up to decide what to do about it.
+pub trait RiskRule: fmt::Debug {
+    fn name(&self) -> &'static str;
+    // How far back, in seconds, the rule looks at the account's activity.
+    fn window(&self) -> u64;
+    // Zero for nothing out of the ordinary.
+    fn score(&self, context: &Context) -> u32;
+}
+
+// The recent deposits and disputes of an account, as seen by the risk rules.
+// Those at an unknown time are never out of any window, so they are only
+// added up rather than kept, or every row of an input without timestamps
+// would be.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    deposits: VecDeque<(Timestamp, Decimal)>,
+    disputes: VecDeque<Timestamp>,
+    untimed_deposited: Decimal,
+    untimed_disputes: usize,
+}
+
+impl Activity {
+    // Takes note of an applied transaction at `now`, forgetting whatever is
+    // outside of the window.
+    pub fn observe(&mut self, transaction: &Transaction, now: Option<Timestamp>, window: u64) {
+        let window = Some(window);
+        while let Some((then, _)) = self.deposits.front() {
+            if !has_elapsed(Some(*then), now, window) {
+                break;
+            }
+            self.deposits.pop_front();
+        }
+        while let Some(then) = self.disputes.front() {
+            if !has_elapsed(Some(*then), now, window) {
+                break;
+            }
+            self.disputes.pop_front();
+        }
+        match (transaction, now) {
+            (
+                Transaction::Deposit {
+                    amount: Some(amount),
+                    ..
+                },
+                Some(now),
+            ) => self.deposits.push_back((now, *amount)),
+            (
+                Transaction::Deposit {
+                    amount: Some(amount),
+                    ..
+                },
+                None,
+            ) => self.untimed_deposited += *amount,
+            (Transaction::Dispute { .. }, Some(now)) => self.disputes.push_back(now),
+            (Transaction::Dispute { .. }, None) => self.untimed_disputes += 1,
+            _ => (),
+        }
+    }
+
+    // How much was deposited within the window before `now`.
+    pub fn deposited_within(&self, now: Option<Timestamp>, window: u64) -> Decimal {
+        self.untimed_deposited
+            + self
+                .deposits
+                .iter()
+                .filter(|(then, _)| !has_elapsed(Some(*then), now, Some(window)))
+                .map(|(_, amount)| *amount)
+                .sum::<Decimal>()
+    }
+
+    // How many disputes there were within the window before `now`.
+    pub fn disputes_within(&self, now: Option<Timestamp>, window: u64) -> usize {
+        self.untimed_disputes
+            + self
+                .disputes
+                .iter()
+                .filter(|then| !has_elapsed(Some(**then), now, Some(window)))
+                .count()
+    }
+}
+
+// A withdrawal of at least percentage% of what was deposited within the last
+// days, i.e money passing straight through the account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeWithdrawalAfterDeposit {
+    pub days: u64,
+    pub percentage: Decimal,
+    pub score: u32,
+}
+
+impl RiskRule for LargeWithdrawalAfterDeposit {
+    fn name(&self) -> &'static str {
+        "large_withdrawal_after_deposit"
+    }
+
+    fn window(&self) -> u64 {
+        self.days * DAY
+    }
+
+    fn score(&self, context: &Context) -> u32 {
+        let amount = match context.transaction {
+            Transaction::Withdrawal {
+                amount: Some(amount),
+                ..
+            } => *amount,
+            _ => return 0,
+        };
+        let deposited = context
+            .activity
+            .deposited_within(context.now, self.window());
+        if deposited > Decimal::ZERO && amount * Decimal::ONE_HUNDRED >= deposited * self.percentage
+        {
+            self.score
+        } else {
+            0
+        }
+    }
+}
+
+// The count:th dispute or more within the last days.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RapidDisputes {
+    pub days: u64,
+    pub count: usize,
+    pub score: u32,
+}
+
+impl RiskRule for RapidDisputes {
+    fn name(&self) -> &'static str {
+        "rapid_disputes"
+    }
+
+    fn window(&self) -> u64 {
+        self.days * DAY
+    }
+
+    fn score(&self, context: &Context) -> u32 {
+        if !matches!(context.transaction, Transaction::Dispute { .. }) {
+            return 0;
+        }
+        if context.activity.disputes_within(context.now, self.window()) + 1 >= self.count {
+            self.score
+        } else {
+            0
+        }
+    }
+}
+
+// What the rules made of a transaction scoring enough to do something about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assessment {
+    pub score: u32,
+    pub action: Action,
+    // The rules that scored it.
+    pub rules: Vec<&'static str>,
+}
+
+// The error a transaction is rejected with when it is too risky, so that it
+// can be told apart from other rejections by downcasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskRejected {
+    pub client: u16,
+    pub tx: u32,
+    pub score: u32,
+}
+
+impl fmt::Display for RiskRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client {}: tx {} rejected with a risk score of {}",
+            self.client, self.tx, self.score
+        )
+    }
+}
+
+impl Error for RiskRejected {}
+
+// The error a transaction is set aside with when it is risky enough to hold,
+// so that it can be told apart from rejections by downcasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldForReview {
+    pub client: u16,
+    pub tx: u32,
+    pub score: u32,
+}
+
+impl fmt::Display for HeldForReview {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client {}: tx {} held for review with a risk score of {}",
+            self.client, self.tx, self.score
+        )
+    }
+}
+
+impl Error for HeldForReview {}
+
+// The risk rules along with the scores at which a transaction is flagged,
+// held or rejected. Any rule can be plugged in with with_rule.
+#[derive(Debug)]
+pub struct RiskPolicy {
+    rules: Vec<Box<dyn RiskRule>>,
+    flag_at: u32,
+    hold_at: u32,
+    reject_at: u32,
+}
+
+impl Default for RiskPolicy {
+    fn default() -> Self {
+        RiskPolicy {
+            rules: Vec::new(),
+            flag_at: FLAG_AT,
+            hold_at: HOLD_AT,
+            reject_at: REJECT_AT,
+        }
+    }
+}
+
+impl RiskPolicy {
+    pub fn with_rule(mut self, rule: Box<dyn RiskRule>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn with_thresholds(mut self, flag_at: u32, hold_at: u32, reject_at: u32) -> Self {
+        self.flag_at = flag_at;
+        self.hold_at = hold_at;
+        self.reject_at = reject_at;
+        self
+    }
+
+    // How far back any rule looks, if there are any rules at all.
+    pub fn window(&self) -> Option<u64> {
+        self.rules.iter().map(|rule| rule.window()).max()
+    }
+
+    // Scores a transaction, returning what to do about it unless it scores too
+    // little to bother.
+    pub fn assess(&self, context: &Context) -> Option<Assessment> {
+        let mut score = 0;
+        let mut rules = Vec::new();
+        for rule in &self.rules {
+            let points = rule.score(context);
+            if points > 0 {
+                score += points;
+                rules.push(rule.name());
+            }
+        }
+        let action = if score >= self.reject_at {
+            Action::Reject
+        } else if score >= self.hold_at {
+            Action::Hold
+        } else if score >= self.flag_at {
+            Action::Flag
+        } else {
+            return None;
+        };
+        Some(Assessment {
+            score,
+            action,
+            rules,
+        })
+    }
+}
+
+// An assessment of a transaction flattened into a csv row.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub seq: u64,
+    pub timestamp: Option<Timestamp>,
+    pub client: u16,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub tx: u32,
+    pub score: u32,
+    pub action: Action,
+    // The rules that scored the transaction, separated by semicolons.
+    pub rules: String,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client {}: {} tx {} scored {} ({}), {:?}",
+            self.client, self.kind, self.tx, self.score, self.rules, self.action
+        )
+    }
+}
+
+// The risk rules file has a row per rule, naming the rule, how many days back
+// it looks, its threshold and what it scores when the threshold is reached, e.g:
+//
+// rule,                           days, threshold, score
+// large_withdrawal_after_deposit,    1,        80,    60
+// rapid_disputes,                    7,         3,    50
+//
+// The threshold is a percentage of what was deposited for
+// large_withdrawal_after_deposit and a number of disputes for rapid_disputes.
+#[derive(Deserialize, Debug)]
+struct RuleEntry {
+    rule: String,
+    days: u64,
+    threshold: Decimal,
+    score: u32,
+}
+
+impl TryFrom<RuleEntry> for Box<dyn RiskRule> {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: RuleEntry) -> Result<Self> {
+        match entry.rule.as_str() {
+            "large_withdrawal_after_deposit" => Ok(Box::new(LargeWithdrawalAfterDeposit {
+                days: entry.days,
+                percentage: entry.threshold,
+                score: entry.score,
+            })),
+            "rapid_disputes" => Ok(Box::new(RapidDisputes {
+                days: entry.days,
+                count: entry.threshold.try_into().map_err(|_| {
+                    anyhow!("rapid_disputes expects a number of disputes as threshold")
+                })?,
+                score: entry.score,
+            })),
+            rule => Err(anyhow!("unknown risk rule {}", rule)),
+        }
+    }
+}
+
+pub fn load_risk_rules<P: AsRef<Path>>(path: P) -> Result<RiskPolicy> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut policy = RiskPolicy::default();
+    for result in reader.deserialize::<RuleEntry>() {
+        policy = policy.with_rule(result?.try_into()?);
+    }
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Action, Activity, Assessment, Context, LargeWithdrawalAfterDeposit, RapidDisputes,
+        RiskPolicy,
+    };
+    use crate::account::Balances;
+    use crate::clock::{Timestamp, DAY};
+    use crate::Transaction;
+
+    fn assess(
+        policy: &RiskPolicy,
+        activity: &Activity,
+        transaction: Transaction,
+        now: Timestamp,
+    ) -> Option<Assessment> {
+        policy.assess(&Context {
+            transaction: &transaction,
+            now: Some(now),
+            balances: Balances::default(),
+            activity,
+        })
+    }
+
+    #[test]
+    fn scores_add_up_to_what_is_done_about_a_transaction() {
+        let policy = RiskPolicy::default()
+            .with_rule(Box::new(LargeWithdrawalAfterDeposit {
+                days: 1,
+                percentage: 80.into(),
+                score: 60,
+            }))
+            .with_rule(Box::new(RapidDisputes {
+                days: 7,
+                count: 2,
+                score: 80,
+            }))
+            .with_thresholds(50, 75, 100);
+        let window = policy.window().unwrap();
+        let withdrawal = |amount: i64| Transaction::Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: Some(amount.into()),
+        };
+        let dispute = Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        let mut activity = Activity::default();
+        let deposit = Transaction::Deposit {
+            client: 1,
+            tx: 1,
+            amount: Some(100.into()),
+        };
+        activity.observe(&deposit, Some(0), window);
+        assert_eq!(assess(&policy, &activity, withdrawal(79), 0), None);
+        let assessment = assess(&policy, &activity, withdrawal(80), 0).unwrap();
+        assert_eq!(assessment.action, Action::Flag);
+        assert_eq!(assessment.rules, vec!["large_withdrawal_after_deposit"]);
+        // The deposit is too long ago a couple of days later.
+        assert_eq!(assess(&policy, &activity, withdrawal(80), 2 * DAY), None);
+        assert_eq!(assess(&policy, &activity, dispute.clone(), 0), None);
+        activity.observe(&dispute, Some(0), window);
+        let assessment = assess(&policy, &activity, dispute, DAY).unwrap();
+        assert_eq!(assessment.action, Action::Hold);
+    }
+
+    #[test]
+    fn activity_at_an_unknown_time_is_added_up_rather_than_kept() {
+        let mut activity = Activity::default();
+        for tx in 1..=1000 {
+            let deposit = Transaction::Deposit {
+                client: 1,
+                tx,
+                amount: Some(1.into()),
+            };
+            activity.observe(&deposit, None, DAY);
+        }
+        let dispute = Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+        };
+        activity.observe(&dispute, None, DAY);
+        assert!(activity.deposits.is_empty() && activity.disputes.is_empty());
+        assert_eq!(activity.deposited_within(None, DAY), 1000.into());
+        assert_eq!(activity.disputes_within(Some(100 * DAY), DAY), 1);
+    }
+}
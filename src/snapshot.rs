@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs::File, io, path::Path};
+
+use crate::account::{Account, Balances};
+#[cfg(test)]
+use crate::{engine::Engine, transaction::TimedTransaction, Transaction};
+
+// A row of account balances as written by paperstack, or by anyone else using
+// the same columns. Any other columns are ignored.
+#[derive(Deserialize, Debug)]
+struct BalanceRow {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+// Reads balances by client from a csv of accounts.
+pub fn read_balances(reader: impl io::Read) -> Result<BTreeMap<u16, Balances>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut balances = BTreeMap::new();
+    for result in reader.deserialize::<BalanceRow>() {
+        let row = result?;
+        let duplicate = balances.insert(
+            row.client,
+            Balances {
+                available: row.available,
+                held: row.held,
+                total: row.total,
+                locked: row.locked,
+            },
+        );
+        if duplicate.is_some() {
+            return Err(anyhow!("client {} appears more than once", row.client));
+        }
+    }
+    Ok(balances)
+}
+
+pub fn load_balances<P: AsRef<Path>>(path: P) -> Result<BTreeMap<u16, Balances>> {
+    read_balances(File::open(path)?)
+}
+
+// The balances of the given accounts by client, to compare with loaded ones.
+pub fn balances_of<'a>(accounts: impl Iterator<Item = &'a Account>) -> BTreeMap<u16, Balances> {
+    accounts
+        .map(|account| (*account.get_client(), account.balances()))
+        .collect()
+}
+
+// How a client's balances changed from one set of balances to another, a side
+// without the client counting as all zero.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Change {
+    pub client: u16,
+    pub before: Option<Balances>,
+    pub after: Option<Balances>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+impl Change {
+    // Whether the client was locked or unlocked, a client that isn't there
+    // being neither.
+    pub fn locked_changed(&self) -> bool {
+        self.before.is_some_and(|balances| balances.locked)
+            != self.after.is_some_and(|balances| balances.locked)
+    }
+
+    pub fn largest_amount(&self) -> Decimal {
+        self.available
+            .abs()
+            .max(self.held.abs())
+            .max(self.total.abs())
+    }
+}
+
+// Every client whose balances differ between before and after, by client.
+pub fn changes(before: &BTreeMap<u16, Balances>, after: &BTreeMap<u16, Balances>) -> Vec<Change> {
+    let mut clients: Vec<_> = before.keys().chain(after.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    let mut changes = Vec::new();
+    for client in clients {
+        let (before, after) = (before.get(&client).copied(), after.get(&client).copied());
+        if before == after {
+            continue;
+        }
+        let (from, to) = (before.unwrap_or_default(), after.unwrap_or_default());
+        changes.push(Change {
+            client,
+            before,
+            after,
+            available: to.available - from.available,
+            held: to.held - from.held,
+            total: to.total - from.total,
+        });
+    }
+    changes
+}
+
+// The balances of every client once the transactions have been applied, for
+// tests comparing one set of balances with another.
+#[cfg(test)]
+pub fn balances_after(transactions: Vec<Transaction>) -> Result<BTreeMap<u16, Balances>> {
+    let mut engine = Engine::default();
+    for transaction in transactions {
+        engine.apply(TimedTransaction {
+            transaction,
+            timestamp: None,
+        })?;
+    }
+    Ok(balances_of(engine.accounts()))
+}
+
+// A deposit of amount for the client, with the client's id as tx id.
+#[cfg(test)]
+pub fn deposit(client: u16, amount: Decimal) -> Transaction {
+    Transaction::Deposit {
+        client,
+        tx: client.into(),
+        amount: Some(amount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changes, read_balances};
+    use crate::account::Balances;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn balances_are_read_from_paperstack_output() -> anyhow::Result<()> {
+        let output = "client,available,held,total,locked,debt,overdraft
+            2, 1.5000, 0.5000, 2.0000, false, 0.0000, 0.0000
+            1, 0.0000, 0.0000, 0.0000, true, 0.0000, 0.0000";
+        let balances = read_balances(output.as_bytes())?;
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[&2].total, 2.into());
+        assert!(balances[&1].locked);
+        assert!(read_balances("client,available\n1,0".as_bytes()).is_err());
+        assert!(read_balances(
+            "client,available,held,total,locked\n1,0,0,0,false\n1,0,0,0,false".as_bytes()
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn only_clients_that_differ_have_changed() {
+        let balances = |available: i64, locked| Balances {
+            available: available.into(),
+            held: 0.into(),
+            total: available.into(),
+            locked,
+        };
+        let before = BTreeMap::from([(1, balances(1, false)), (2, balances(2, false))]);
+        let after = BTreeMap::from([
+            (1, balances(1, false)),
+            (2, balances(2, true)),
+            (3, balances(-3, false)),
+        ]);
+        let changes = changes(&before, &after);
+        assert_eq!(changes.len(), 2);
+        assert!(changes[0].locked_changed());
+        assert!(changes[0].total.is_zero());
+        assert_eq!(changes[1].before, None);
+        assert_eq!(changes[1].largest_amount(), 3.into());
+    }
+}
@@ -0,0 +1,354 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+use crate::account::{serialize_with_fixed_digits, Balances};
+use crate::clock::Timestamp;
+use crate::history::{HistoryEntry, Outcome};
+use crate::Transaction;
+
+// The period a statement covers, from (inclusive) to (exclusive). Leaving out
+// either end makes the period start at the beginning or run to the end.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub from: Option<Timestamp>,
+    pub to: Option<Timestamp>,
+}
+
+impl Period {
+    fn is_before(&self, timestamp: Option<Timestamp>) -> bool {
+        self.from.is_some_and(|from| timestamp < Some(from))
+    }
+
+    fn is_after(&self, timestamp: Option<Timestamp>) -> bool {
+        self.to.is_some_and(|to| timestamp >= Some(to))
+    }
+}
+
+// A client's statement for a period, worked out from the account's history.
+// Only applied transactions make it onto a statement.
+#[derive(Debug)]
+pub struct Statement<'a> {
+    client: u16,
+    period: Period,
+    opening: Balances,
+    lines: Vec<&'a HistoryEntry>,
+    // Deposits under dispute at the end of the period and the amount held for them.
+    open_disputes: BTreeMap<u32, Decimal>,
+    closing: Balances,
+}
+
+impl<'a> Statement<'a> {
+    pub fn new(client: u16, history: &'a [HistoryEntry], period: Period) -> Self {
+        let mut statement = Statement {
+            client,
+            period,
+            opening: Balances::default(),
+            lines: Vec::new(),
+            open_disputes: BTreeMap::new(),
+            closing: Balances::default(),
+        };
+        for entry in history {
+            if period.is_after(entry.timestamp) {
+                break;
+            }
+            if entry.outcome != Outcome::Applied {
+                continue;
+            }
+            match entry.transaction {
+                Transaction::Dispute { tx, .. } | Transaction::Representment { tx, .. } => {
+                    statement
+                        .open_disputes
+                        .insert(tx, entry.after.held - entry.before.held);
+                }
+                Transaction::Resolve { tx, .. }
+                | Transaction::Chargeback { tx, .. }
+                | Transaction::PreArbitration { tx, .. } => {
+                    statement.open_disputes.remove(&tx);
+                }
+                _ => (),
+            }
+            if period.is_before(entry.timestamp) {
+                statement.opening = entry.after;
+            } else {
+                statement.lines.push(entry);
+            }
+            statement.closing = entry.after;
+        }
+        statement
+    }
+
+    // The statement as csv rows, opening with the opening balance and closing
+    // with the open disputes and the closing balance.
+    pub fn rows(&self) -> Vec<StatementRow> {
+        let balance = |kind, balances: &Balances| StatementRow {
+            client: self.client,
+            seq: None,
+            timestamp: None,
+            kind,
+            tx: None,
+            amount: None,
+            available: balances.available,
+            held: balances.held,
+            total: balances.total,
+            locked: balances.locked,
+        };
+        let mut rows = vec![balance("opening_balance", &self.opening)];
+        for entry in &self.lines {
+            rows.push(StatementRow {
+                seq: Some(entry.seq),
+                timestamp: entry.timestamp,
+                tx: Some(*entry.transaction.get_tx()),
+                amount: *entry.transaction.get_amount(),
+                ..balance(entry.transaction.get_kind(), &entry.after)
+            });
+        }
+        for (tx, held) in &self.open_disputes {
+            rows.push(StatementRow {
+                tx: Some(*tx),
+                amount: Some(*held),
+                ..balance("open_dispute", &self.closing)
+            });
+        }
+        rows.push(balance("closing_balance", &self.closing));
+        rows
+    }
+}
+
+// A line of a statement flattened into a csv row. The balances are those after
+// the line.
+#[derive(Serialize, Debug)]
+pub struct StatementRow {
+    client: u16,
+    seq: Option<u64>,
+    timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    tx: Option<u32>,
+    amount: Option<Decimal>,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    available: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    held: Decimal,
+    #[serde(serialize_with = "serialize_with_fixed_digits")]
+    total: Decimal,
+    locked: bool,
+}
+
+fn write_period(f: &mut fmt::Formatter, period: &Period) -> fmt::Result {
+    match period.from {
+        Some(from) => write!(f, "from {}", from)?,
+        None => write!(f, "from the start")?,
+    }
+    match period.to {
+        Some(to) => write!(f, " until {}", to),
+        None => write!(f, " until the end"),
+    }
+}
+
+fn write_balances(f: &mut fmt::Formatter, balances: &Balances) -> fmt::Result {
+    write!(
+        f,
+        "available {:.4}, held {:.4}, total {:.4}",
+        balances.available, balances.held, balances.total
+    )?;
+    if balances.locked {
+        write!(f, ", locked")?;
+    }
+    Ok(())
+}
+
+impl<'a> fmt::Display for Statement<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "statement for client {} ", self.client)?;
+        write_period(f, &self.period)?;
+        writeln!(f)?;
+        writeln!(f)?;
+        write!(f, "opening balance: ")?;
+        write_balances(f, &self.opening)?;
+        writeln!(f)?;
+        for entry in &self.lines {
+            write!(
+                f,
+                "#{} {} tx {}",
+                entry.seq,
+                entry.transaction.get_kind(),
+                entry.transaction.get_tx()
+            )?;
+            if let Some(amount) = entry.transaction.get_amount() {
+                write!(f, " of {:.4}", amount)?;
+            }
+            write!(f, ": ")?;
+            write_balances(f, &entry.after)?;
+            writeln!(f)?;
+        }
+        for (tx, held) in &self.open_disputes {
+            writeln!(f, "open dispute of tx {}: {:.4} held", tx, held)?;
+        }
+        write!(f, "closing balance: ")?;
+        write_balances(f, &self.closing)?;
+        writeln!(f)
+    }
+}
+
+// Any number of statements as a single html document.
+pub struct Html<'a>(pub &'a [Statement<'a>]);
+
+impl<'a> fmt::Display for Html<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "<!DOCTYPE html>")?;
+        writeln!(f, "<html>")?;
+        writeln!(
+            f,
+            "<head><meta charset=\"utf-8\"><title>Statements</title></head>"
+        )?;
+        writeln!(f, "<body>")?;
+        for statement in self.0 {
+            write!(f, "<h1>Statement for client {}</h1>\n<p>", statement.client)?;
+            write_period(f, &statement.period)?;
+            writeln!(f, "</p>")?;
+            writeln!(f, "<table>")?;
+            writeln!(
+                f,
+                "<tr><th>#</th><th>time</th><th>type</th><th>tx</th><th>amount</th>\
+                 <th>available</th><th>held</th><th>total</th><th>locked</th></tr>"
+            )?;
+            for row in statement.rows() {
+                let optional = |value: Option<String>| value.unwrap_or_default();
+                writeln!(
+                    f,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                     <td>{:.4}</td><td>{:.4}</td><td>{:.4}</td><td>{}</td></tr>",
+                    optional(row.seq.map(|seq| seq.to_string())),
+                    optional(row.timestamp.map(|timestamp| timestamp.to_string())),
+                    row.kind.replace('_', " "),
+                    optional(row.tx.map(|tx| tx.to_string())),
+                    optional(row.amount.map(|amount| format!("{:.4}", amount))),
+                    row.available,
+                    row.held,
+                    row.total,
+                    if row.locked { "yes" } else { "no" }
+                )?;
+            }
+            writeln!(f, "</table>")?;
+        }
+        writeln!(f, "</body>")?;
+        writeln!(f, "</html>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Period, Statement};
+    use crate::account::Balances;
+    use crate::engine::{Config, Engine};
+    use crate::history::HistoryScope;
+    use crate::transaction::TimedTransaction;
+    use crate::Transaction;
+    use anyhow::Result;
+
+    // An engine with the history of client 1, who deposits 10 at 10 and 5 at 30,
+    // disputing the first deposit at 20 and resolving that dispute at 40.
+    fn engine() -> Result<Engine<'static>> {
+        let mut engine = Engine::new(Config {
+            history: HistoryScope::Client(1),
+            ..Default::default()
+        });
+        let transactions = [
+            (
+                10,
+                Transaction::Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: Some(10.into()),
+                },
+            ),
+            (
+                20,
+                Transaction::Dispute {
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                },
+            ),
+            (
+                30,
+                Transaction::Deposit {
+                    client: 1,
+                    tx: 3,
+                    amount: Some(5.into()),
+                },
+            ),
+            (
+                40,
+                Transaction::Resolve {
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                },
+            ),
+        ];
+        for (timestamp, transaction) in transactions {
+            engine.apply(TimedTransaction {
+                transaction,
+                timestamp: Some(timestamp),
+            })?;
+        }
+        Ok(engine)
+    }
+
+    fn balances(available: i64, held: i64) -> Balances {
+        Balances {
+            available: available.into(),
+            held: held.into(),
+            total: (available + held).into(),
+            locked: false,
+        }
+    }
+
+    #[test]
+    fn a_statement_covers_only_its_period() -> Result<()> {
+        let engine = engine()?;
+        let account = engine.account(1).expect("account 1 to exist");
+        let statement = Statement::new(
+            1,
+            account.get_history(),
+            Period {
+                from: Some(15),
+                to: Some(40),
+            },
+        );
+        assert_eq!(statement.opening, balances(10, 0));
+        assert_eq!(statement.lines.len(), 2);
+        assert_eq!(statement.lines[0].seq, 2);
+        assert_eq!(statement.open_disputes.get(&1), Some(&10.into()));
+        assert_eq!(statement.closing, balances(5, 10));
+        let rows = statement.rows();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0].kind, "opening_balance");
+        assert_eq!(rows[3].kind, "open_dispute");
+        assert_eq!(rows[4].kind, "closing_balance");
+        Ok(())
+    }
+
+    #[test]
+    fn an_empty_period_closes_at_its_opening_balance() -> Result<()> {
+        let engine = engine()?;
+        let account = engine.account(1).expect("account 1 to exist");
+        let statement = Statement::new(
+            1,
+            account.get_history(),
+            Period {
+                from: Some(50),
+                to: None,
+            },
+        );
+        assert!(statement.lines.is_empty());
+        assert!(statement.open_disputes.is_empty());
+        assert_eq!(statement.opening, account.balances());
+        assert_eq!(statement.opening, balances(15, 0));
+        assert_eq!(statement.closing, statement.opening);
+        assert!(statement.to_string().contains("from 50 until the end"));
+        Ok(())
+    }
+}
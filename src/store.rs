@@ -0,0 +1,429 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::clock::{has_elapsed, Timestamp};
+use crate::transaction::TimedTransaction;
+use crate::Transaction;
+
+// Deposits are kept around in case they get disputed or reversed, and so are
+// withdrawals in case they get reversed or refunded. As tx ids are unique
+// across clients, a single store keyed by tx id serves every account.
+pub trait DepositStore: Debug {
+    fn get(&mut self, tx: u32) -> Result<Option<TimedTransaction>>;
+    // Like get, but leaves the store exactly as it was, e.g for a dry run.
+    fn peek(&self, tx: u32) -> Result<Option<TimedTransaction>>;
+    fn insert(&mut self, tx: u32, deposit: TimedTransaction) -> Result<()>;
+    // Forgets deposits that happened more than retention ago, unless they are
+    // under dispute.
+    fn sweep(&mut self, now: Option<Timestamp>, retention: Option<u64>) -> Result<()>;
+}
+
+fn is_expired(deposit: &TimedTransaction, now: Option<Timestamp>, retention: Option<u64>) -> bool {
+    !matches!(deposit.transaction, Transaction::Dispute { .. })
+        && has_elapsed(deposit.timestamp, now, retention)
+}
+
+// Keeps every deposit in memory. Memory grows with the number of deposits
+// (minus whatever is swept) which is fine unless the input is huge.
+#[derive(Default, Debug)]
+pub struct MemoryStore {
+    deposits: HashMap<u32, TimedTransaction>,
+}
+
+impl DepositStore for MemoryStore {
+    fn get(&mut self, tx: u32) -> Result<Option<TimedTransaction>> {
+        self.peek(tx)
+    }
+    fn peek(&self, tx: u32) -> Result<Option<TimedTransaction>> {
+        Ok(self.deposits.get(&tx).cloned())
+    }
+    fn insert(&mut self, tx: u32, deposit: TimedTransaction) -> Result<()> {
+        self.deposits.insert(tx, deposit);
+        Ok(())
+    }
+    fn sweep(&mut self, now: Option<Timestamp>, retention: Option<u64>) -> Result<()> {
+        self.deposits
+            .retain(|_, deposit| !is_expired(deposit, now, retention));
+        Ok(())
+    }
+}
+
+// A view of another store that keeps its own writes, leaving the store underneath
+// as it was. Used to try out transactions without committing them, so the store
+// underneath is only ever peeked at.
+#[derive(Debug)]
+pub struct Overlay<'a> {
+    base: &'a dyn DepositStore,
+    // Deposits written, or forgotten, through the overlay by tx id.
+    writes: HashMap<u32, Option<TimedTransaction>>,
+    // The last sweep, kept so that deposits in the base can be expired as they are read.
+    swept: (Option<Timestamp>, Option<u64>),
+}
+
+impl<'a> Overlay<'a> {
+    pub fn new(base: &'a dyn DepositStore) -> Self {
+        Overlay {
+            base,
+            writes: HashMap::new(),
+            swept: (None, None),
+        }
+    }
+}
+
+impl DepositStore for Overlay<'_> {
+    fn get(&mut self, tx: u32) -> Result<Option<TimedTransaction>> {
+        self.peek(tx)
+    }
+    fn peek(&self, tx: u32) -> Result<Option<TimedTransaction>> {
+        if let Some(deposit) = self.writes.get(&tx) {
+            return Ok(deposit.clone());
+        }
+        let deposit = self.base.peek(tx)?;
+        Ok(deposit.filter(|deposit| !is_expired(deposit, self.swept.0, self.swept.1)))
+    }
+    fn insert(&mut self, tx: u32, deposit: TimedTransaction) -> Result<()> {
+        self.writes.insert(tx, Some(deposit));
+        Ok(())
+    }
+    fn sweep(&mut self, now: Option<Timestamp>, retention: Option<u64>) -> Result<()> {
+        for deposit in self.writes.values_mut() {
+            if deposit
+                .as_ref()
+                .is_some_and(|deposit| is_expired(deposit, now, retention))
+            {
+                *deposit = None;
+            }
+        }
+        self.swept = (now, retention);
+        Ok(())
+    }
+}
+
+// Keeps the most recently used deposits in memory and spills the rest to disk,
+// so that memory stays flat however many deposits there are.
+#[derive(Debug)]
+pub struct TieredStore {
+    capacity: usize,
+    memory: HashMap<u32, (TimedTransaction, u64)>,
+    // Memory tier tx ids by when they were last used, oldest first.
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+    disk: DiskTier,
+    // The last sweep, kept so that deposits on disk can be expired as they are read.
+    swept: (Option<Timestamp>, Option<u64>),
+}
+
+impl TieredStore {
+    pub fn new<P: AsRef<Path>>(path: P, capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(anyhow!("deposit cache must hold at least one deposit"));
+        }
+        Ok(TieredStore {
+            capacity,
+            memory: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            disk: DiskTier::create(path)?,
+            swept: (None, None),
+        })
+    }
+    fn touch(&mut self, tx: u32, deposit: TimedTransaction) {
+        self.tick += 1;
+        if let Some((_, used)) = self.memory.insert(tx, (deposit, self.tick)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, tx);
+    }
+    // Moves the least recently used deposits to disk until the memory tier fits.
+    fn spill(&mut self) -> Result<()> {
+        while self.memory.len() > self.capacity {
+            let Some((_, tx)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((deposit, _)) = self.memory.remove(&tx) {
+                self.disk.write(tx, Some(&deposit))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DepositStore for TieredStore {
+    fn get(&mut self, tx: u32) -> Result<Option<TimedTransaction>> {
+        if let Some((deposit, _)) = self.memory.get(&tx) {
+            let deposit = deposit.clone();
+            self.touch(tx, deposit.clone());
+            return Ok(Some(deposit));
+        }
+        match self.disk.read(tx)? {
+            Some(deposit) if is_expired(&deposit, self.swept.0, self.swept.1) => {
+                self.disk.write(tx, None)?;
+                Ok(None)
+            }
+            Some(deposit) => {
+                self.touch(tx, deposit.clone());
+                self.spill()?;
+                Ok(Some(deposit))
+            }
+            None => Ok(None),
+        }
+    }
+    // Doesn't count as a use, nor drops an expired deposit from disk.
+    fn peek(&self, tx: u32) -> Result<Option<TimedTransaction>> {
+        if let Some((deposit, _)) = self.memory.get(&tx) {
+            return Ok(Some(deposit.clone()));
+        }
+        let deposit = self.disk.read(tx)?;
+        Ok(deposit.filter(|deposit| !is_expired(deposit, self.swept.0, self.swept.1)))
+    }
+    fn insert(&mut self, tx: u32, deposit: TimedTransaction) -> Result<()> {
+        self.touch(tx, deposit);
+        self.spill()
+    }
+    // Only the memory tier is swept. Scanning the disk tier would mean reading
+    // every deposit ever made, so expired deposits there are dropped when read.
+    fn sweep(&mut self, now: Option<Timestamp>, retention: Option<u64>) -> Result<()> {
+        let expired: Vec<(u32, u64)> = self
+            .memory
+            .iter()
+            .filter(|(_, (deposit, _))| is_expired(deposit, now, retention))
+            .map(|(tx, (_, used))| (*tx, *used))
+            .collect();
+        for (tx, used) in expired {
+            self.memory.remove(&tx);
+            self.recency.remove(&used);
+        }
+        self.swept = (now, retention);
+        Ok(())
+    }
+}
+
+// The disk tier is a single file of fixed size records addressed directly by
+// tx id, i.e the record for tx n lives at offset n * RECORD_LEN. No index is
+// needed and, as the file is sparse, only written records take up disk space.
+//
+// A record is laid out as:
+//
+// 0      kind (0 means there is no deposit)
+// 1      flags (1 = has amount, 2 = has timestamp)
+// 2..4   client
+// 8..24  amount
+// 24..32 timestamp
+#[derive(Debug)]
+struct DiskTier {
+    file: File,
+}
+
+const RECORD_LEN: u64 = 32;
+const HAS_AMOUNT: u8 = 1;
+const HAS_TIMESTAMP: u8 = 2;
+
+impl DiskTier {
+    fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(DiskTier { file })
+    }
+    fn write(&mut self, tx: u32, deposit: Option<&TimedTransaction>) -> Result<()> {
+        let mut record = [0u8; RECORD_LEN as usize];
+        if let Some(deposit) = deposit {
+            encode(deposit, &mut record)?;
+        }
+        self.file.seek(SeekFrom::Start(tx as u64 * RECORD_LEN))?;
+        self.file.write_all(&record)?;
+        Ok(())
+    }
+    // Reads through a shared reference to the file, so that peeking at the
+    // store doesn't need it to be mutable.
+    fn read(&self, tx: u32) -> Result<Option<TimedTransaction>> {
+        let mut record = [0u8; RECORD_LEN as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(tx as u64 * RECORD_LEN))?;
+        match file.read_exact(&mut record) {
+            Ok(()) => decode(tx, &record),
+            // Past the end of the file, i.e never written.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn encode(deposit: &TimedTransaction, record: &mut [u8; RECORD_LEN as usize]) -> Result<()> {
+    let transaction = &deposit.transaction;
+    record[0] = match transaction {
+        Transaction::Deposit { .. } => 1,
+        Transaction::Dispute { .. } => 2,
+        Transaction::Resolve { .. } => 3,
+        Transaction::Chargeback { .. } => 4,
+        Transaction::Withdrawal { .. } => 5,
+        Transaction::Reversal { .. } => 6,
+        Transaction::Refund { .. } => 7,
+        _ => return Err(anyhow!("cannot store {} as a deposit", transaction)),
+    };
+    record[2..4].copy_from_slice(&transaction.get_client().to_le_bytes());
+    if let Some(amount) = transaction.get_amount() {
+        record[1] |= HAS_AMOUNT;
+        record[8..24].copy_from_slice(&amount.serialize());
+    }
+    if let Some(timestamp) = deposit.timestamp {
+        record[1] |= HAS_TIMESTAMP;
+        record[24..32].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    Ok(())
+}
+
+fn decode(tx: u32, record: &[u8; RECORD_LEN as usize]) -> Result<Option<TimedTransaction>> {
+    let client = u16::from_le_bytes([record[2], record[3]]);
+    let amount = if record[1] & HAS_AMOUNT != 0 {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&record[8..24]);
+        Some(Decimal::deserialize(bytes))
+    } else {
+        None
+    };
+    let timestamp = if record[1] & HAS_TIMESTAMP != 0 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&record[24..32]);
+        Some(Timestamp::from_le_bytes(bytes))
+    } else {
+        None
+    };
+    let transaction = match record[0] {
+        0 => return Ok(None),
+        1 => Transaction::Deposit { client, tx, amount },
+        2 => Transaction::Dispute { client, tx, amount },
+        3 => Transaction::Resolve { client, tx, amount },
+        4 => Transaction::Chargeback { client, tx, amount },
+        5 => Transaction::Withdrawal { client, tx, amount },
+        6 => Transaction::Reversal { client, tx, amount },
+        7 => Transaction::Refund { client, tx, amount },
+        kind => return Err(anyhow!("corrupt deposit record {} of kind {}", tx, kind)),
+    };
+    Ok(Some(TimedTransaction {
+        transaction,
+        timestamp,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DepositStore, MemoryStore, Overlay, TieredStore};
+    use crate::clock::DAY;
+    use crate::transaction::TimedTransaction;
+    use crate::Transaction;
+    use anyhow::Result;
+    use rust_decimal::Decimal;
+    use std::{env, fs, path::PathBuf};
+
+    fn deposit(tx: u32, timestamp: Option<u64>) -> TimedTransaction {
+        TimedTransaction {
+            transaction: Transaction::Deposit {
+                client: 7,
+                tx,
+                amount: Some(Decimal::new(12345, 4)),
+            },
+            timestamp,
+        }
+    }
+
+    // A tiered store on a file of its own, to be removed by the test.
+    fn tiered(name: &str, capacity: usize) -> Result<(TieredStore, PathBuf)> {
+        let path = env::temp_dir().join(format!("paperstack-{}-{}", name, std::process::id()));
+        Ok((TieredStore::new(&path, capacity)?, path))
+    }
+
+    #[test]
+    fn the_memory_store_sweeps_expired_deposits_but_not_disputed_ones() -> Result<()> {
+        let mut store = MemoryStore::default();
+        store.insert(1, deposit(1, Some(0)))?;
+        store.insert(2, deposit(2, Some(0)))?;
+        let mut disputed = deposit(2, Some(0));
+        disputed.transaction.dispute(7)?;
+        store.insert(2, disputed.clone())?;
+        store.sweep(Some(2 * DAY), Some(DAY))?;
+        assert_eq!(store.get(1)?, None);
+        assert_eq!(store.get(2)?, Some(disputed));
+        Ok(())
+    }
+
+    #[test]
+    fn the_tiered_store_spills_to_disk_and_reads_back() -> Result<()> {
+        let (mut store, path) = tiered("spill", 2)?;
+        for tx in 1..=10 {
+            store.insert(
+                tx,
+                deposit(tx, if tx % 2 == 0 { Some(tx as u64) } else { None }),
+            )?;
+        }
+        assert_eq!(store.memory.len(), 2);
+        for tx in 1..=10 {
+            assert_eq!(
+                store.get(tx)?,
+                Some(deposit(
+                    tx,
+                    if tx % 2 == 0 { Some(tx as u64) } else { None }
+                ))
+            );
+        }
+        assert_eq!(store.get(11)?, None);
+        assert_eq!(store.get(u32::MAX)?, None);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn the_tiered_store_keeps_updates_to_spilled_deposits() -> Result<()> {
+        let (mut store, path) = tiered("update", 1)?;
+        store.insert(1, deposit(1, None))?;
+        store.insert(2, deposit(2, None))?;
+        let mut disputed = store.get(1)?.expect("deposit 1 to be stored");
+        disputed.transaction.dispute(7)?;
+        store.insert(1, disputed.clone())?;
+        store.insert(3, deposit(3, None))?;
+        assert_eq!(store.get(1)?, Some(disputed));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn the_tiered_store_expires_deposits_on_disk_when_read() -> Result<()> {
+        let (mut store, path) = tiered("expire", 1)?;
+        store.insert(1, deposit(1, Some(0)))?;
+        store.insert(2, deposit(2, Some(2 * DAY)))?;
+        store.sweep(Some(2 * DAY), Some(DAY))?;
+        assert_eq!(store.get(1)?, None);
+        assert_eq!(store.get(2)?, Some(deposit(2, Some(2 * DAY))));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reading_through_an_overlay_leaves_the_tiered_store_as_it_was() -> Result<()> {
+        let (mut store, path) = tiered("overlay", 1)?;
+        store.insert(1, deposit(1, Some(0)))?;
+        store.insert(2, deposit(2, Some(0)))?;
+        store.insert(3, deposit(3, Some(2 * DAY)))?;
+        store.sweep(Some(2 * DAY), Some(DAY))?;
+        let mut overlay = Overlay::new(&store);
+        // Deposit 2 has expired on disk and 3 is the only one in memory.
+        assert_eq!(overlay.get(2)?, None);
+        assert_eq!(overlay.get(3)?, Some(deposit(3, Some(2 * DAY))));
+        overlay.insert(4, deposit(4, Some(2 * DAY)))?;
+        assert_eq!(store.tick, 3);
+        assert_eq!(store.memory.keys().collect::<Vec<_>>(), vec![&3]);
+        assert_eq!(store.disk.read(2)?, Some(deposit(2, Some(0))));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
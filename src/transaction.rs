@@ -4,6 +4,8 @@ use anyhow::{anyhow, Result};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
+use crate::clock::Timestamp;
+
 // Why do we have this "intermediate" representation?
 // I.e why not deserialize directly into a Transaction?
 // Because: https://github.com/BurntSushi/rust-csv/issues/211
@@ -14,6 +16,10 @@ struct TransactionEntry {
     client: u16,
     tx: u32,
     amount: Option<Decimal>,
+    // Seconds since the unix epoch. The column is optional.
+    timestamp: Option<Timestamp>,
+    // Free text for the dispute case the transaction belongs to. The column is optional.
+    note: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -24,9 +30,24 @@ enum TransactionEntryKind {
     Dispute,
     Resolve,
     Chargeback,
+    Overdraft,
+    Authorize,
+    Capture,
+    Release,
+    Reversal,
+    Refund,
+    LegalHold,
+    LegalRelease,
+    ReviewRelease,
+    Inquiry,
+    Representment,
+    PreArbitration,
+    Arbitration,
+    Note,
+    ReserveRelease,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(from = "TransactionEntry")]
 pub enum Transaction {
     Deposit {
@@ -54,6 +75,120 @@ pub enum Transaction {
         tx: u32,
         amount: Option<Decimal>,
     },
+    // Admin transaction setting the client's overdraft limit to amount.
+    Overdraft {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Holds amount under the authorization id tx until it is captured or released.
+    // When tracked by an account, amount is what is still held.
+    Authorize {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Finalizes amount (or all of it if there is no amount) of the authorization tx
+    // as a withdrawal.
+    Capture {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Returns whatever is left of the authorization tx to the available funds.
+    Release {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Voids amount (or all that is left) of the deposit or withdrawal tx. When
+    // tracked in the store, a fully reversed transaction, with nothing left.
+    Reversal {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Credits amount (or all that is left) of the withdrawal tx back to the
+    // client. When tracked in the store, a fully refunded withdrawal.
+    Refund {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Credits amount of accrued interest. These are made by the engine rather
+    // than read from the input, and tx is always 0.
+    Interest {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Holds amount of the available funds under the legal hold id tx, e.g by
+    // court order, until it is released.
+    LegalHold {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Releases the legal hold tx.
+    LegalRelease {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Lets the transaction tx through once it has been reviewed, see the risk module.
+    ReviewRelease {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Locks the account of a client whose chargeback ratio turned critical with
+    // the dispute tx. These are only ever made by the engine.
+    Lock {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Opens a case for the deposit tx without holding anything, as an inquiry
+    // comes before any dispute. See the case module.
+    Inquiry {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Contests the chargeback of the deposit tx, holding what was charged back
+    // under the dispute again until the case is resolved or goes to pre-arbitration.
+    Representment {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Rejects the representment of the deposit tx, charging it back again.
+    PreArbitration {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Closes the case of the deposit tx with a ruling that awards amount (or
+    // nothing if there is no amount) of what was charged back to the client.
+    Arbitration {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Adds the note of its row to the case of the deposit tx, changing nothing else.
+    Note {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
+    // Releases what was reserved from the deposit tx to the available funds.
+    // These are made by the engine once the reserve period is over, or given by
+    // hand for deposits without a known time.
+    ReserveRelease {
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    },
 }
 
 impl Eq for Transaction {}
@@ -86,6 +221,91 @@ impl fmt::Display for Transaction {
                 "Chargeback [ client: {}, tx: {}, amount: {:?} ]",
                 client, tx, amount
             ),
+            Transaction::Overdraft { client, tx, amount } => write!(
+                f,
+                "Overdraft [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Release { client, tx, amount } => write!(
+                f,
+                "Release [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Capture { client, tx, amount } => write!(
+                f,
+                "Capture [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Authorize { client, tx, amount } => write!(
+                f,
+                "Authorize [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Reversal { client, tx, amount } => write!(
+                f,
+                "Reversal [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Refund { client, tx, amount } => write!(
+                f,
+                "Refund [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Interest { client, tx, amount } => write!(
+                f,
+                "Interest [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::LegalHold { client, tx, amount } => write!(
+                f,
+                "LegalHold [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::LegalRelease { client, tx, amount } => write!(
+                f,
+                "LegalRelease [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::ReviewRelease { client, tx, amount } => write!(
+                f,
+                "ReviewRelease [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Lock { client, tx, amount } => write!(
+                f,
+                "Lock [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Inquiry { client, tx, amount } => write!(
+                f,
+                "Inquiry [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Representment { client, tx, amount } => write!(
+                f,
+                "Representment [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::PreArbitration { client, tx, amount } => write!(
+                f,
+                "PreArbitration [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Arbitration { client, tx, amount } => write!(
+                f,
+                "Arbitration [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::Note { client, tx, amount } => write!(
+                f,
+                "Note [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
+            Transaction::ReserveRelease { client, tx, amount } => write!(
+                f,
+                "ReserveRelease [ client: {}, tx: {}, amount: {:?} ]",
+                client, tx, amount
+            ),
         }
     }
 }
@@ -118,6 +338,115 @@ impl From<TransactionEntry> for Transaction {
                 tx: te.tx,
                 amount: te.amount,
             },
+            TransactionEntryKind::Overdraft => Transaction::Overdraft {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Authorize => Transaction::Authorize {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Capture => Transaction::Capture {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Release => Transaction::Release {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Reversal => Transaction::Reversal {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Refund => Transaction::Refund {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::LegalHold => Transaction::LegalHold {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::LegalRelease => Transaction::LegalRelease {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::ReviewRelease => Transaction::ReviewRelease {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Inquiry => Transaction::Inquiry {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Representment => Transaction::Representment {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::PreArbitration => Transaction::PreArbitration {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Arbitration => Transaction::Arbitration {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::Note => Transaction::Note {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+            TransactionEntryKind::ReserveRelease => Transaction::ReserveRelease {
+                client: te.client,
+                tx: te.tx,
+                amount: te.amount,
+            },
+        }
+    }
+}
+
+// A transaction along with when it happened, if we know.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "TransactionEntry")]
+pub struct TimedTransaction {
+    pub transaction: Transaction,
+    pub timestamp: Option<Timestamp>,
+}
+
+impl From<TransactionEntry> for TimedTransaction {
+    fn from(te: TransactionEntry) -> Self {
+        TimedTransaction {
+            timestamp: te.timestamp,
+            transaction: te.into(),
+        }
+    }
+}
+
+// A timed transaction along with the note of its row, if it has one.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "TransactionEntry")]
+pub struct NotedTransaction {
+    pub timed: TimedTransaction,
+    pub note: Option<String>,
+}
+
+impl From<TransactionEntry> for NotedTransaction {
+    fn from(mut te: TransactionEntry) -> Self {
+        NotedTransaction {
+            note: te.note.take().filter(|note| !note.is_empty()),
+            timed: te.into(),
         }
     }
 }
@@ -130,6 +459,76 @@ impl Transaction {
             Transaction::Dispute { client, .. } => client,
             Transaction::Resolve { client, .. } => client,
             Transaction::Chargeback { client, .. } => client,
+            Transaction::Overdraft { client, .. } => client,
+            Transaction::Authorize { client, .. } => client,
+            Transaction::Capture { client, .. } => client,
+            Transaction::Release { client, .. } => client,
+            Transaction::Reversal { client, .. } => client,
+            Transaction::Refund { client, .. } => client,
+            Transaction::Interest { client, .. } => client,
+            Transaction::ReserveRelease { client, .. } => client,
+            Transaction::LegalHold { client, .. } => client,
+            Transaction::LegalRelease { client, .. } => client,
+            Transaction::ReviewRelease { client, .. } => client,
+            Transaction::Lock { client, .. } => client,
+            Transaction::Inquiry { client, .. } => client,
+            Transaction::Representment { client, .. } => client,
+            Transaction::PreArbitration { client, .. } => client,
+            Transaction::Arbitration { client, .. } => client,
+            Transaction::Note { client, .. } => client,
+        }
+    }
+    pub fn get_tx(&self) -> &u32 {
+        match self {
+            Transaction::Deposit { tx, .. } => tx,
+            Transaction::Withdrawal { tx, .. } => tx,
+            Transaction::Dispute { tx, .. } => tx,
+            Transaction::Resolve { tx, .. } => tx,
+            Transaction::Chargeback { tx, .. } => tx,
+            Transaction::Overdraft { tx, .. } => tx,
+            Transaction::Authorize { tx, .. } => tx,
+            Transaction::Capture { tx, .. } => tx,
+            Transaction::Release { tx, .. } => tx,
+            Transaction::Reversal { tx, .. } => tx,
+            Transaction::Refund { tx, .. } => tx,
+            Transaction::Interest { tx, .. } => tx,
+            Transaction::ReserveRelease { tx, .. } => tx,
+            Transaction::LegalHold { tx, .. } => tx,
+            Transaction::LegalRelease { tx, .. } => tx,
+            Transaction::ReviewRelease { tx, .. } => tx,
+            Transaction::Lock { tx, .. } => tx,
+            Transaction::Inquiry { tx, .. } => tx,
+            Transaction::Representment { tx, .. } => tx,
+            Transaction::PreArbitration { tx, .. } => tx,
+            Transaction::Arbitration { tx, .. } => tx,
+            Transaction::Note { tx, .. } => tx,
+        }
+    }
+    // The name of the kind of transaction, as given in the input.
+    pub fn get_kind(&self) -> &'static str {
+        match self {
+            Transaction::Deposit { .. } => "deposit",
+            Transaction::Withdrawal { .. } => "withdrawal",
+            Transaction::Dispute { .. } => "dispute",
+            Transaction::Resolve { .. } => "resolve",
+            Transaction::Chargeback { .. } => "chargeback",
+            Transaction::Overdraft { .. } => "overdraft",
+            Transaction::Authorize { .. } => "authorize",
+            Transaction::Capture { .. } => "capture",
+            Transaction::Release { .. } => "release",
+            Transaction::Reversal { .. } => "reversal",
+            Transaction::Refund { .. } => "refund",
+            Transaction::Interest { .. } => "interest",
+            Transaction::ReserveRelease { .. } => "reserve_release",
+            Transaction::LegalHold { .. } => "legal_hold",
+            Transaction::LegalRelease { .. } => "legal_release",
+            Transaction::ReviewRelease { .. } => "review_release",
+            Transaction::Lock { .. } => "lock",
+            Transaction::Inquiry { .. } => "inquiry",
+            Transaction::Representment { .. } => "representment",
+            Transaction::PreArbitration { .. } => "pre_arbitration",
+            Transaction::Arbitration { .. } => "arbitration",
+            Transaction::Note { .. } => "note",
         }
     }
     pub fn get_amount(&self) -> &Option<Decimal> {
@@ -139,6 +538,23 @@ impl Transaction {
             Transaction::Dispute { amount, .. } => amount,
             Transaction::Resolve { amount, .. } => amount,
             Transaction::Chargeback { amount, .. } => amount,
+            Transaction::Overdraft { amount, .. } => amount,
+            Transaction::Authorize { amount, .. } => amount,
+            Transaction::Capture { amount, .. } => amount,
+            Transaction::Release { amount, .. } => amount,
+            Transaction::Reversal { amount, .. } => amount,
+            Transaction::Refund { amount, .. } => amount,
+            Transaction::Interest { amount, .. } => amount,
+            Transaction::ReserveRelease { amount, .. } => amount,
+            Transaction::LegalHold { amount, .. } => amount,
+            Transaction::LegalRelease { amount, .. } => amount,
+            Transaction::ReviewRelease { amount, .. } => amount,
+            Transaction::Lock { amount, .. } => amount,
+            Transaction::Inquiry { amount, .. } => amount,
+            Transaction::Representment { amount, .. } => amount,
+            Transaction::PreArbitration { amount, .. } => amount,
+            Transaction::Arbitration { amount, .. } => amount,
+            Transaction::Note { amount, .. } => amount,
         }
     }
     // Only deposits can be disputed.
@@ -210,12 +626,174 @@ impl Transaction {
             self
         ))
     }
+    // Only authorizations can be captured, for more than nothing but never for
+    // more than they hold. Returns what is left of the authorization afterwards.
+    pub fn capture(&mut self, from_client: u16, captured: Decimal) -> Result<Decimal> {
+        if let Transaction::Authorize { client, tx, amount } = self {
+            if *client != from_client {
+                return Err(anyhow!(
+                    "cannot capture authorization {} belonging to client {} as client {}",
+                    tx,
+                    client,
+                    from_client
+                ));
+            };
+            let held = amount.ok_or_else(|| anyhow!("authorization {} missing amount", tx))?;
+            if captured <= Decimal::ZERO || captured > held {
+                return Err(anyhow!(
+                    "cannot capture {:.4} of authorization {} holding {:.4}",
+                    captured,
+                    tx,
+                    held
+                ));
+            }
+            *amount = Some(held - captured);
+            return Ok(held - captured);
+        }
+        Err(anyhow!(
+            "only authorizations can be captured but {} is not an authorization",
+            self
+        ))
+    }
+    // Deposits and withdrawals can be reversed, in part or in full, unless
+    // under dispute or charged back. A deposit whose dispute was resolved is a
+    // deposit like any other. Returns the amount reversed.
+    pub fn reverse(&mut self, from_client: u16, reversed: Option<Decimal>) -> Result<Decimal> {
+        match self {
+            Transaction::Deposit { client, tx, amount }
+            | Transaction::Resolve { client, tx, amount }
+            | Transaction::Withdrawal { client, tx, amount } => {
+                if *client != from_client {
+                    return Err(anyhow!(
+                        "cannot reverse transaction {} belonging to client {} as client {}",
+                        tx,
+                        client,
+                        from_client
+                    ));
+                }
+                let reversed = take(amount, *tx, reversed, "reverse")?;
+                if amount.is_some_and(|left| left.is_zero()) {
+                    *self = Transaction::Reversal {
+                        client: *client,
+                        tx: *tx,
+                        amount: *amount,
+                    };
+                }
+                Ok(reversed)
+            }
+            Transaction::Dispute { tx, .. } => Err(anyhow!(
+                "cannot reverse transaction {} while it is under dispute",
+                tx
+            )),
+            Transaction::Chargeback { tx, .. } => Err(anyhow!(
+                "cannot reverse transaction {} as it has been charged back",
+                tx
+            )),
+            _ => Err(anyhow!(
+                "only deposits and withdrawals can be reversed but {} is neither",
+                self
+            )),
+        }
+    }
+    // Only withdrawals can be refunded and never for more than is left of them.
+    // Returns the amount refunded.
+    pub fn refund(&mut self, from_client: u16, refunded: Option<Decimal>) -> Result<Decimal> {
+        if let Transaction::Withdrawal { client, tx, amount } = self {
+            if *client != from_client {
+                return Err(anyhow!(
+                    "cannot refund withdrawal {} belonging to client {} as client {}",
+                    tx,
+                    client,
+                    from_client
+                ));
+            }
+            let refunded = take(amount, *tx, refunded, "refund")?;
+            if amount.is_some_and(|left| left.is_zero()) {
+                *self = Transaction::Refund {
+                    client: *client,
+                    tx: *tx,
+                    amount: *amount,
+                };
+            }
+            return Ok(refunded);
+        }
+        Err(anyhow!(
+            "only withdrawals can be refunded but {} is not a withdrawal",
+            self
+        ))
+    }
+}
+
+// Takes taken (or all of it if None) out of amount, which may not go below zero.
+fn take(
+    amount: &mut Option<Decimal>,
+    tx: u32,
+    taken: Option<Decimal>,
+    what: &str,
+) -> Result<Decimal> {
+    let left = amount.ok_or_else(|| anyhow!("transaction {} missing amount", tx))?;
+    let taken = taken.unwrap_or(left);
+    if taken <= Decimal::ZERO || taken > left {
+        return Err(anyhow!(
+            "cannot {} {:.4} of transaction {} with {:.4} left",
+            what,
+            taken,
+            tx,
+            left
+        ));
+    }
+    *amount = Some(left - taken);
+    Ok(taken)
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::Transaction;
+    use super::{NotedTransaction, TimedTransaction, Transaction};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn the_timestamp_column_is_optional() -> Result<(), csv::Error> {
+        let input = "type,client,tx,amount,timestamp\ndeposit,1,1,1.5,100\ndeposit,1,2,1.5,\n";
+        let timed = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<TimedTransaction>()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(timed[0].timestamp, Some(100));
+        assert_eq!(timed[1].timestamp, None);
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let timed = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<TimedTransaction>()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            timed[0],
+            TimedTransaction {
+                transaction: Transaction::Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: Some(Decimal::new(15, 1)),
+                },
+                timestamp: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn the_note_column_is_optional_and_empty_notes_are_none() -> Result<(), csv::Error> {
+        let input = "type,client,tx,amount,note\ndispute,1,1,,stolen card\ndeposit,1,2,1.5,\n";
+        let noted = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<NotedTransaction>()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(noted[0].note.as_deref(), Some("stolen card"));
+        assert_eq!(noted[1].note, None);
+        let input = "type,client,tx,amount\ninquiry,1,1,\n";
+        let noted = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<NotedTransaction>()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(noted[0].note, None);
+        assert_eq!(noted[0].timed.transaction.get_kind(), "inquiry");
+        Ok(())
+    }
 
     #[test]
     fn a_deposit_can_be_turned_into_a_dispute() {
@@ -389,4 +967,98 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn an_authorization_can_be_captured_in_parts() {
+        let mut transaction = Transaction::Authorize {
+            amount: Some(10.into()),
+            client: 1,
+            tx: 1,
+        };
+        assert_eq!(transaction.capture(1, 4.into()).ok(), Some(6.into()));
+        assert_eq!(transaction.capture(1, 6.into()).ok(), Some(Decimal::ZERO));
+        assert_eq!(transaction.get_amount(), &Some(Decimal::ZERO));
+    }
+
+    #[test]
+    fn capturing_more_than_an_authorization_holds_fails() {
+        let mut transaction = Transaction::Authorize {
+            amount: Some(10.into()),
+            client: 1,
+            tx: 1,
+        };
+        assert!(transaction.capture(1, 11.into()).is_err());
+        assert!(transaction.capture(2, 1.into()).is_err());
+        assert_eq!(transaction.get_amount(), &Some(10.into()));
+    }
+
+    #[test]
+    fn only_authorizations_can_be_captured() {
+        let mut transaction = Transaction::Deposit {
+            amount: Some(10.into()),
+            client: 1,
+            tx: 1,
+        };
+        assert!(transaction.capture(1, 1.into()).is_err());
+    }
+
+    #[test]
+    fn a_transaction_is_reversed_or_refunded_until_nothing_is_left() -> anyhow::Result<()> {
+        let mut transaction = Transaction::Withdrawal {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        };
+        assert!(transaction.reverse(2, None).is_err());
+        assert_eq!(transaction.reverse(1, Some(4.into()))?, 4.into());
+        assert!(transaction.refund(1, Some(7.into())).is_err());
+        assert_eq!(transaction.refund(1, None)?, 6.into());
+        assert_eq!(
+            transaction,
+            Transaction::Refund {
+                client: 1,
+                tx: 1,
+                amount: Some(Decimal::ZERO)
+            }
+        );
+        assert!(transaction.reverse(1, None).is_err());
+        let mut disputed = Transaction::Dispute {
+            client: 1,
+            tx: 2,
+            amount: Some(10.into()),
+        };
+        assert!(disputed.reverse(1, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn a_reserve_release_can_be_given_by_hand() -> Result<(), csv::Error> {
+        let input = "type,client,tx,amount\nreserve_release,1,1,\n";
+        let timed = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<TimedTransaction>()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            timed[0].transaction,
+            Transaction::ReserveRelease {
+                client: 1,
+                tx: 1,
+                amount: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn a_deposit_whose_dispute_was_resolved_can_be_reversed() -> anyhow::Result<()> {
+        let mut resolved = Transaction::Resolve {
+            client: 1,
+            tx: 1,
+            amount: Some(10.into()),
+        };
+        assert_eq!(resolved.reverse(1, Some(4.into()))?, 4.into());
+        assert_eq!(resolved.get_amount(), &Some(6.into()));
+        assert_eq!(resolved.reverse(1, None)?, 6.into());
+        assert!(matches!(resolved, Transaction::Reversal { .. }));
+        Ok(())
+    }
 }